@@ -0,0 +1,288 @@
+use derive_more::{Display, From};
+use erroneous::Error as EError;
+use git2::{Oid, Repository};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+use std::{fs, io, path::PathBuf};
+
+use crate::{config::Branch, project::Project, util};
+
+/// Steam's limit on the length of change notes
+pub const MAX_LENGTH: usize = 8000;
+
+/// Where the change notes of a branch come from.
+pub enum Changelog {
+	/// The summaries of the commits since the last publish
+	Git,
+	/// Like `Git`, but grouped by conventional commit type
+	Conventional,
+	/// The matching section of a markdown changelog
+	File(PathBuf),
+}
+
+impl<'a> Deserialize<'a> for Changelog {
+	fn deserialize<D: Deserializer<'a>>(d: D) -> Result<Self, D::Error> {
+		let s = String::deserialize(d)?;
+		Ok(match s.as_str() {
+			"git" => Changelog::Git,
+			"conventional" => Changelog::Conventional,
+			_ => Changelog::File(s.into()),
+		})
+	}
+}
+
+#[derive(Debug, Display, EError, From)]
+pub enum Error {
+	#[display(fmt = "Could not read git history")]
+	Git(#[error(source)] git2::Error),
+	#[display(fmt = "{}", _0)]
+	ReadError(#[error(defer)] util::ReadError),
+	#[display(fmt = "'.published.{}' does not contain a valid commit", _0)]
+	InvalidPublished(String),
+	#[display(fmt = "Could not write to '.published.{}'", _0)]
+	WritePublished(String, #[error(source)] io::Error),
+}
+
+fn published_path(project: &Project, branch_name: &str) -> PathBuf {
+	project.path.join(format!(".published.{}", branch_name))
+}
+
+/// The commit the branch was last published from
+pub fn last_published(project: &Project, branch_name: &str) -> Result<Option<Oid>, Error> {
+	let path = published_path(project, branch_name);
+	if !path.exists() {
+		return Ok(None);
+	}
+	let oid = util::read_to_string(path)?;
+	Oid::from_str(oid.trim())
+		.map(Some)
+		.map_err(|_| Error::InvalidPublished(branch_name.into()))
+}
+
+pub fn set_published(project: &Project, branch_name: &str, commit: Oid) -> Result<(), Error> {
+	fs::write(published_path(project, branch_name), commit.to_string())
+		.map_err(|e| Error::WritePublished(branch_name.into(), e))
+}
+
+/// The summaries of the commits from `since` (exclusive) to `head`, newest first.
+/// Without `since`, only `head` itself is included.
+fn commits(repo: &Repository, head: Oid, since: Option<Oid>) -> Result<Vec<String>, Error> {
+	let since = since.filter(|&since| repo.find_commit(since).is_ok());
+	let since = match since {
+		Some(since) => since,
+		None => {
+			let commit = repo.find_commit(head)?;
+			return Ok(commit.summary().map(String::from).into_iter().collect());
+		},
+	};
+
+	let mut walk = repo.revwalk()?;
+	walk.push(head)?;
+	walk.hide(since)?;
+	let mut summaries = Vec::new();
+	for oid in walk {
+		let commit = repo.find_commit(oid?)?;
+		if commit.parent_count() > 1 {
+			continue;
+		}
+		if let Some(summary) = commit.summary() {
+			summaries.push(summary.into());
+		}
+	}
+	Ok(summaries)
+}
+
+fn list(commits: &[String]) -> String {
+	commits.iter().map(|c| format!("- {}\n", c)).collect()
+}
+
+fn conventional(commits: &[String]) -> String {
+	lazy_static! {
+		static ref CONVENTIONAL_RE: Regex =
+			Regex::new(r"^(\w+)(?:\([^)]*\))?!?:\s*(.*)$").expect("Could not generate regex");
+	}
+
+	const GROUPS: &[(&str, &str)] = &[
+		("feat", "Features"),
+		("fix", "Bug fixes"),
+		("perf", "Performance"),
+	];
+
+	let mut groups = vec![Vec::new(); GROUPS.len() + 1];
+	for commit in commits {
+		let (group, summary) = match CONVENTIONAL_RE.captures(commit) {
+			Some(c) => (
+				GROUPS.iter().position(|&(t, _)| t == &c[1]),
+				c[2].to_owned(),
+			),
+			None => (None, commit.clone()),
+		};
+		groups[group.unwrap_or(GROUPS.len())].push(summary);
+	}
+
+	let headings = GROUPS.iter().map(|&(_, h)| h).chain(Some("Other changes"));
+	headings
+		.zip(groups)
+		.filter(|(_, commits)| !commits.is_empty())
+		.map(|(heading, commits)| format!("{}:\n{}", heading, list(&commits)))
+		.collect::<Vec<_>>()
+		.join("\n")
+}
+
+/// The section of a markdown changelog whose heading mentions `version`,
+/// or the first one if there is no such section.
+fn section<'a>(changelog: &'a str, version: Option<&str>) -> &'a str {
+	let mut lines = Vec::new();
+	let mut offset = 0;
+	for line in changelog.split('\n') {
+		lines.push((offset, line));
+		offset += line.len() + 1;
+	}
+
+	let version = version.map(|v| v.trim_start_matches('v'));
+	let sections: Vec<_> = (0..lines.len())
+		.filter(|&i| lines[i].1.starts_with("## "))
+		.collect();
+	let start = sections
+		.iter()
+		.cloned()
+		.find(|&i| match version {
+			Some(v) => lines[i].1.contains(v),
+			None => false,
+		})
+		.or_else(|| sections.first().cloned());
+	let start = match start {
+		Some(start) => start + 1,
+		None => return changelog.trim(),
+	};
+	let end = (start..lines.len())
+		.find(|&i| lines[i].1.starts_with("# ") || lines[i].1.starts_with("## "))
+		.map_or(changelog.len(), |i| lines[i].0);
+
+	changelog[lines.get(start).map_or(end, |l| l.0)..end].trim()
+}
+
+/// The tag pointing at `head`, if there is one
+fn tag(repo: &Repository, head: Oid) -> Option<String> {
+	let names = repo.tag_names(None).ok()?;
+	let tag = names.iter().flatten().find(|name| {
+		repo.revparse_single(name)
+			.and_then(|o| o.peel_to_commit())
+			.map(|c| c.id() == head)
+			.unwrap_or(false)
+	});
+	tag.map(String::from)
+}
+
+fn truncate(mut s: String) -> String {
+	const ELLIPSIS: &str = "\n...";
+
+	if s.chars().count() <= MAX_LENGTH {
+		return s;
+	}
+	let mut end = s
+		.char_indices()
+		.nth(MAX_LENGTH - ELLIPSIS.len())
+		.map_or(s.len(), |(i, _)| i);
+	// Don't cut a line in half if possible
+	if let Some(newline) = s[..end].rfind('\n') {
+		end = newline;
+	}
+	s.truncate(end);
+	s.push_str(ELLIPSIS);
+	s
+}
+
+/// The workshop change notes for publishing the current state of the branch.
+pub fn change_note(project: &Project, branch: &Branch, branch_name: &str) -> Result<String, Error> {
+	let repo = Repository::open(&project.path).ok();
+	let head = project.head();
+
+	let body = match (&branch.changelog, &repo, head) {
+		(Some(Changelog::File(path)), _, _) => {
+			let changelog = util::read_to_string(project.path.join(path))?;
+			let version = match (&repo, head) {
+				(Some(repo), Some(head)) => tag(repo, head),
+				_ => None,
+			};
+			let section = section(&changelog, version.as_deref());
+			if path.extension() == Some("md".as_ref()) {
+				md_to_bb::convert(section)
+			} else {
+				section.into()
+			}
+		},
+		(changelog, Some(repo), Some(head)) => {
+			let commits = commits(repo, head, last_published(project, branch_name)?)?;
+			match changelog {
+				Some(Changelog::Conventional) => conventional(&commits),
+				_ => list(&commits),
+			}
+		},
+		(_, _, _) => String::new(),
+	};
+
+	let note = match head {
+		Some(head) if body.is_empty() => format!("git commit: {}", head),
+		Some(head) => format!("git commit: {}\n\n{}", head, body.trim_end()),
+		None => body,
+	};
+	Ok(truncate(note))
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+	let commits: Vec<String> = [
+		"feat(ui): add scoreboard",
+		"fix!: crash on join",
+		"Update README",
+		"chore: bump version",
+		"feat: new map",
+	]
+	.iter()
+	.map(|&s| s.into())
+	.collect();
+	assert_eq!(
+		conventional(&commits),
+		"\
+Features:
+- add scoreboard
+- new map
+
+Bug fixes:
+- crash on join
+
+Other changes:
+- Update README
+- bump version
+"
+	);
+
+	const CHANGELOG: &str = "\
+# Changelog
+
+## [Unreleased]
+- nothing yet
+
+## [1.1.0] - 2019-05-01
+### Added
+- scoreboard
+
+## [1.0.0] - 2019-04-01
+- initial release
+";
+	assert_eq!(
+		section(CHANGELOG, Some("v1.1.0")),
+		"### Added\n- scoreboard"
+	);
+	assert_eq!(section(CHANGELOG, Some("2.0.0")), "- nothing yet");
+	assert_eq!(section(CHANGELOG, None), "- nothing yet");
+	assert_eq!(section("no sections", None), "no sections");
+
+	let long = "line\n".repeat(2000);
+	let truncated = truncate(long);
+	assert!(truncated.len() <= MAX_LENGTH);
+	assert!(truncated.ends_with("line\n..."));
+}
@@ -0,0 +1,190 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{fmt, path::PathBuf};
+
+use crate::{
+	config::{Branch, NS2_APP_ID},
+	preview::{self, Format},
+	publish::Artefacts,
+};
+
+/// The longest title the workshop accepts
+pub const MAX_TITLE_LENGTH: usize = 128;
+/// The longest description the workshop accepts
+pub const MAX_DESCRIPTION_LENGTH: usize = 8000;
+
+/// The tags Launch Pad offers for Natural Selection 2 mods
+pub const NS2_TAGS: &[&str] = &[
+	"Gameplay Tweak",
+	"Game Mode",
+	"Map",
+	"Cosmetic",
+	"Sound",
+	"Utility",
+	"Library",
+	"Must be run on Server",
+	"Must be run on Client",
+	"Must be run on Server and Client",
+];
+
+lazy_static! {
+	static ref YOUTUBE_ID_RE: Regex =
+		Regex::new(r"^[A-Za-z0-9_-]{11}$").expect("Could not generate regex");
+}
+
+/// A position in a file, both starting at 1.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+	pub file:   PathBuf,
+	pub line:   usize,
+	/// In characters
+	pub column: usize,
+}
+
+impl fmt::Display for Location {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}:{}:{}", self.file.display(), self.line, self.column)
+	}
+}
+
+/// Something that would make publishing fail or break the workshop page.
+#[derive(Debug)]
+pub struct Problem {
+	/// Where in `laspad.toml` the problem comes from
+	pub field:    String,
+	pub message:  String,
+	/// Where `field` is, if known
+	pub location: Option<Location>,
+}
+
+impl fmt::Display for Problem {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		if let Some(location) = &self.location {
+			write!(f, "{}: ", location)?;
+		}
+		if !self.field.is_empty() {
+			write!(f, "{}: ", self.field)?;
+		}
+		write!(f, "{}", self.message)
+	}
+}
+
+#[derive(Debug)]
+pub struct Problems(pub Vec<Problem>);
+
+impl fmt::Display for Problems {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		for (i, problem) in self.0.iter().enumerate() {
+			if i > 0 {
+				writeln!(f)?;
+			}
+			write!(f, "{}", problem)?;
+		}
+		Ok(())
+	}
+}
+
+/// Checks the artefacts of a branch against the constraints of the workshop.
+pub fn artefacts(branch_name: &str, branch: &Branch, artefacts: &Artefacts) -> Vec<Problem> {
+	let mut problems = Vec::new();
+	let mut problem = |field: &str, message: String| {
+		problems.push(Problem {
+			field:    format!("branch.{}.{}", branch_name, field),
+			message,
+			location: None,
+		})
+	};
+
+	let title = artefacts.title.chars().count();
+	if title == 0 {
+		problem("name", "The title is empty".into());
+	} else if title > MAX_TITLE_LENGTH {
+		problem(
+			"name",
+			format!(
+				"The title is {} characters long, but at most {} are allowed",
+				title, MAX_TITLE_LENGTH
+			),
+		);
+	}
+
+	let description = artefacts.description.chars().count();
+	if description > MAX_DESCRIPTION_LENGTH {
+		problem(
+			if branch.description.is_some() {
+				"description"
+			} else {
+				"description_str"
+			},
+			format!(
+				"The description is {} characters long after conversion, but at most {} are allowed",
+				description, MAX_DESCRIPTION_LENGTH
+			),
+		);
+	}
+
+	if Format::detect(&artefacts.preview).is_none() {
+		problem(
+			"preview",
+			"The preview is not a PNG, JPEG or GIF image".into(),
+		);
+	}
+	if artefacts.preview.len() > preview::MAX_SIZE {
+		problem(
+			"preview",
+			format!(
+				"The preview is {} bytes large, but at most {} are allowed",
+				artefacts.preview.len(),
+				preview::MAX_SIZE
+			),
+		);
+	}
+
+	for screenshot in &artefacts.screenshots {
+		if Format::detect(&screenshot.data).is_none() {
+			problem(
+				"screenshots",
+				format!(
+					"The screenshot '{}' is not a PNG, JPEG or GIF image",
+					screenshot.name
+				),
+			);
+		}
+		if screenshot.data.len() > preview::MAX_SIZE {
+			problem(
+				"screenshots",
+				format!(
+					"The screenshot '{}' is {} bytes large, but at most {} are allowed",
+					screenshot.name,
+					screenshot.data.len(),
+					preview::MAX_SIZE
+				),
+			);
+		}
+	}
+
+	for video in &artefacts.videos {
+		if !YOUTUBE_ID_RE.is_match(video) {
+			problem(
+				"videos",
+				format!("'{}' is not the ID of a YouTube video", video),
+			);
+		}
+	}
+
+	for tag in &artefacts.tags {
+		// Other games have their own tags, which laspad doesn't know
+		if artefacts.app_id == NS2_APP_ID && !NS2_TAGS.contains(&tag.as_str()) {
+			problem(
+				"tags",
+				format!(
+					"'{}' is not a workshop tag of Natural Selection 2, use one of: {}",
+					tag,
+					NS2_TAGS.join(", ")
+				),
+			);
+		}
+	}
+
+	problems
+}
@@ -6,7 +6,7 @@ use std::{
 };
 use walkdir::WalkDir;
 
-use crate::Project;
+use crate::{config::Branch, Project};
 
 #[derive(Debug, Display, EError, From)]
 pub enum Error {
@@ -18,6 +18,10 @@ pub enum Error {
 	Dependencies(#[error(source)] io::Error),
 	#[display(fmt = "Could not compile dependency at '{}'", "_0.display()")]
 	Dependency(PathBuf, #[error(source)] Box<Error>),
+	#[display(fmt = "The dependency at '{}' is missing, run `laspad update`", "_0.display()")]
+	Missing(PathBuf),
+	#[display(fmt = "The overlay '{}' is not a directory", "_0.display()")]
+	NoOverlay(PathBuf),
 }
 
 pub trait Out {
@@ -47,11 +51,20 @@ fn iterate_dir(root: &Path, out: &mut impl Out) -> Result<(), Error> {
 	Ok(())
 }
 
-pub fn compile(project: &Project, out: &mut impl Out) -> Result<(), Error> {
+/// Compiles the dependencies and sources of the project into `out`,
+/// with those of `branch` and its overlays on top if given.
+pub fn compile(
+	project: &Project,
+	branch: Option<&Branch>,
+	out: &mut impl Out,
+) -> Result<(), Error> {
 	info!("Compiling project at {}", project.path.display());
-	for dep in project.dependencies().map_err(Error::Dependencies)? {
+	for dep in project.dependencies(branch).map_err(Error::Dependencies)? {
+		if !dep.path.exists() {
+			return Err(Error::Missing(dep.path));
+		}
 		if let Ok(Some(project)) = Project::get(&dep.path) {
-			compile(&project, out).map_err(|e| Error::Dependency(dep.path.into(), e.into()))?;
+			compile(&project, None, out).map_err(|e| Error::Dependency(dep.path.into(), e.into()))?;
 		} else {
 			let src = ["source", "output", "src"]
 				.iter()
@@ -85,5 +98,13 @@ pub fn compile(project: &Project, out: &mut impl Out) -> Result<(), Error> {
 	if let Some(src) = src {
 		iterate_dir(&src, out)?;
 	}
+	for overlay in branch.and_then(|b| b.overlays.as_ref()).into_iter().flatten() {
+		let overlay = project.path.join(overlay);
+		if !overlay.is_dir() {
+			return Err(Error::NoOverlay(overlay));
+		}
+		info!("Compiling overlay in {}", overlay.display());
+		iterate_dir(&overlay, out)?;
+	}
 	Ok(())
 }
@@ -1,30 +1,128 @@
 use derive_more::{Display, From};
 use erroneous::Error as EError;
 use joinery::Joinable;
-use serde_derive::Deserialize;
+use serde_derive::{Deserialize, Serialize};
 use std::{
 	borrow::Cow,
 	collections::HashMap,
 	ffi::OsStr,
+	fmt,
 	fs,
 	io,
 	path::{Path, PathBuf},
 	str::FromStr,
 };
-use toml;
+use toml::{self, value::Table};
 
-use crate::{item::Item, project::Project, util};
+use crate::{changelog::Changelog, guard::Guard, item::Item, project::Project, util};
+
+/// The Steam app ID of Natural Selection 2, which mods are published for by default
+pub const NS2_APP_ID: u32 = 4920;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+	Public,
+	Friends,
+	Private,
+	Unlisted,
+}
+
+impl Visibility {
+	/// The corresponding `ERemoteStoragePublishedFileVisibility`
+	pub fn as_steam(self) -> u32 {
+		match self {
+			Visibility::Public => 0,
+			Visibility::Friends => 1,
+			Visibility::Private => 2,
+			Visibility::Unlisted => 3,
+		}
+	}
+}
 
 #[derive(Deserialize)]
 pub struct Branch {
-	pub name:            String,
-	pub tags:            Vec<String>,
-	pub autodescription: Option<bool>,
-	pub description:     Option<PathBuf>,
-	pub description_str: Option<String>,
-	pub preview:         Option<PathBuf>,
-	pub website:         Option<String>,
-	pub item:            Option<Item>,
+	pub name:                String,
+	pub tags:                Vec<String>,
+	pub autodescription:     Option<bool>,
+	pub description:         Option<PathBuf>,
+	pub description_str:     Option<String>,
+	pub preview:             Option<PathBuf>,
+	pub website:             Option<String>,
+	pub item:                Option<Item>,
+	pub visibility:          Option<Visibility>,
+	pub changelog:           Option<Changelog>,
+	pub guard:               Option<Guard>,
+	/// Overrides the app ID of the project
+	pub app_id:              Option<u32>,
+	/// Paths or glob patterns of extra preview images, in the order they are shown
+	pub screenshots:         Option<Vec<String>>,
+	/// IDs of YouTube videos shown on the workshop page
+	pub videos:              Option<Vec<String>>,
+	/// Mods shipped in this branch in addition to the project's dependencies
+	pub dependencies:        Option<Vec<Dep>>,
+	/// Dependencies of the project this branch ships without
+	pub remove_dependencies: Option<Vec<Dep>>,
+	/// Source directories whose files are layered on top of `src`, in order
+	pub overlays:            Option<Vec<PathBuf>>,
+}
+
+/// The keys of a branch table, which `laspad check` reports other keys against
+pub const BRANCH_KEYS: &[&str] = &[
+	"name",
+	"tags",
+	"autodescription",
+	"description",
+	"description_str",
+	"preview",
+	"website",
+	"item",
+	"visibility",
+	"changelog",
+	"guard",
+	"app_id",
+	"screenshots",
+	"videos",
+	"dependencies",
+	"remove_dependencies",
+	"overlays",
+];
+
+/// A mod the project depends on, as declared in `dependencies`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum Dep {
+	/// A workshop item, by Mod ID or URL
+	Workshop(Item),
+	/// A git repository, cloned by `laspad update`
+	Git {
+		git:    String,
+		/// A commit, tag or branch. If `None`, the revision in `laspad.lock`,
+		/// or the default branch of the remote when it isn't locked or is updated explicitly
+		rev:    Option<String>,
+		/// The directory of the mod within the repository
+		subdir: Option<PathBuf>,
+	},
+	/// A directory, relative to the project
+	Path { path: PathBuf },
+}
+
+/// An extra preview image of a branch.
+pub struct Screenshot {
+	pub name: String,
+	pub data: Vec<u8>,
+}
+
+#[derive(Debug, Display, EError)]
+pub enum ScreenshotError {
+	#[display(fmt = "'{}' is not a valid glob pattern", _0)]
+	Pattern(String, #[error(source)] glob::PatternError),
+	#[display(fmt = "Could not list the files matching '{}'", _0)]
+	Glob(String, #[error(source)] glob::GlobError),
+	#[display(fmt = "No screenshot matches '{}'", _0)]
+	NoMatch(String),
+	#[display(fmt = "Could not read screenshot '{}'", "_0.display()")]
+	Read(PathBuf, #[error(source)] io::Error),
 }
 
 #[derive(Debug, Display, EError, From)]
@@ -70,13 +168,16 @@ impl Branch {
 				"".into()
 			};
 
-			let deps = project.dependencies().map_err(Dependencies)?;
+			let deps = project.dependencies(Some(self)).map_err(Dependencies)?;
 
 			let deps = if deps.len() > 0 {
 				let deps: Result<Vec<_>, DescriptionError> = deps
 					.into_iter()
 					.map(|d| match d.url() {
-						Some(url) => Ok(Some(format!("  [*] [url={}]{}[/url]\n", url, d.name()?))),
+						Some(url) => {
+							let rev = d.rev.map_or("".into(), |r| format!(" at {}", r));
+							Ok(Some(format!("  [*] [url={}]{}[/url]{}\n", url, d.name()?, rev)))
+						},
 						None => Ok(None),
 					})
 					.collect();
@@ -107,12 +208,43 @@ impl Branch {
 			fs::read(project.path.join(preview))
 		})
 	}
+
+	/// Reads the screenshots, each pattern's matches sorted by path.
+	/// A pattern that doesn't match anything is most likely a typo, so it is an error.
+	pub fn screenshots(&self, project: &Project) -> Result<Vec<Screenshot>, ScreenshotError> {
+		use self::ScreenshotError::*;
+
+		let mut screenshots = Vec::new();
+		for pattern in self.screenshots.iter().flatten() {
+			let full = project.path.join(pattern).to_string_lossy().into_owned();
+			let mut paths = glob::glob(&full)
+				.map_err(|e| Pattern(pattern.clone(), e))?
+				.collect::<Result<Vec<_>, _>>()
+				.map_err(|e| Glob(pattern.clone(), e))?;
+			if paths.is_empty() {
+				return Err(NoMatch(pattern.clone()));
+			}
+			paths.sort();
+			for path in paths {
+				let data = fs::read(&path).map_err(|e| Read(path.clone(), e))?;
+				screenshots.push(Screenshot {
+					name: path
+						.file_name()
+						.map_or_else(String::new, |n| n.to_string_lossy().into_owned()),
+					data,
+				});
+			}
+		}
+		Ok(screenshots)
+	}
 }
 
 pub struct Config {
-	pub deps:              Vec<Item>,
+	pub deps:              Vec<Dep>,
 	pub branches:          HashMap<String, Branch>,
 	pub source_output_dir: Option<(PathBuf, PathBuf)>,
+	/// The app ID of the game the mod is for, `NS2_APP_ID` if `None`
+	pub app_id:            Option<u32>,
 }
 
 #[derive(Debug, Display, EError, From)]
@@ -121,8 +253,65 @@ pub enum ParseError {
 	InvalidVersion(i64),
 	#[display(fmt = "Expected key {} of type {}", _0, _1)]
 	ExpectedKey(&'static str, &'static str),
-	#[display(fmt = "Could not parse ")]
+	#[display(fmt = "Invalid TOML or values, `laspad check` shows where")]
 	Error(#[error(source)] toml::de::Error),
+	#[display(fmt = "The branch '{}' extends the unknown branch '{}'", branch, parent)]
+	UnknownParent { branch: String, parent: String },
+	#[display(fmt = "The branches extend each other in a cycle: {}", _0)]
+	InheritanceCycle(String),
+}
+
+/// Merges `overrides` into `base`, recursing into tables that are in both.
+fn merge(base: &mut Table, overrides: Table) {
+	for (key, value) in overrides {
+		match value {
+			toml::Value::Table(value) => match base.get_mut(&key) {
+				Some(toml::Value::Table(base)) => merge(base, value),
+				_ => {
+					base.insert(key, toml::Value::Table(value));
+				},
+			},
+			value => {
+				base.insert(key, value);
+			},
+		}
+	}
+}
+
+/// The table of a version 2 branch with the tables of the branches it `extends`
+/// and the project's `defaults` merged into it, the nearest one taking precedence.
+/// Only `item` is never inherited, since every branch is a workshop item of its own.
+fn inherit(defaults: &Table, branches: &Table, name: &str) -> Result<toml::Value, ParseError> {
+	let mut chain = vec![name];
+	while let Some(parent) = branches[chain[chain.len() - 1]]
+		.get("extends")
+		.and_then(|e| e.as_str())
+	{
+		if !branches.contains_key(parent) {
+			return Err(ParseError::UnknownParent {
+				branch: chain[chain.len() - 1].into(),
+				parent: parent.into(),
+			});
+		}
+		let cycle = chain.contains(&parent);
+		chain.push(parent);
+		if cycle {
+			return Err(ParseError::InheritanceCycle(chain.join(" -> ")));
+		}
+	}
+
+	let mut merged = defaults.clone();
+	for name in chain.iter().rev() {
+		if let toml::Value::Table(table) = &branches[*name] {
+			let mut table = table.clone();
+			table.remove("extends");
+			if *name == chain[0] {
+				merged.remove("item");
+			}
+			merge(&mut merged, table);
+		}
+	}
+	Ok(toml::Value::Table(merged))
 }
 
 #[derive(Debug, Display, EError, From)]
@@ -136,15 +325,63 @@ pub enum TOMLGetError {
 #[derive(Debug, Display, EError, From)]
 pub enum LuaGetError {
 	#[display(fmt = "Could not parse mod.settings")]
-	Parse,
+	Parse(#[error(source)] static_lua::Error),
 	#[display(fmt = "Could not read mod.settings")]
 	Read(#[error(source)] io::Error),
 	#[display(fmt = "Key '{}' is missing from mod.settings", _0)]
 	MissingKey(&'static str),
+	#[display(fmt = "Key '{}' of mod.settings should be a string, but is a {}", _0, _1)]
+	NotAString(String, &'static str),
 	#[display(fmt = "'publish_id' field has an invalid format! It should be hexadecimal")]
 	InvalidPublishId,
 }
 
+/// A branch that isn't in the configuration.
+#[derive(Debug, EError)]
+pub struct UnknownBranch {
+	pub name:       String,
+	pub available:  Vec<String>,
+	/// The available branch with the most similar name, if any is similar enough
+	pub suggestion: Option<String>,
+}
+
+impl fmt::Display for UnknownBranch {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "There is no branch '{}'", self.name)?;
+		if let Some(suggestion) = &self.suggestion {
+			write!(f, ", did you mean '{}'?", suggestion)?;
+		}
+		write!(f, " The branches are: {}", self.available.join(", "))
+	}
+}
+
+/// The number of single character insertions, deletions and substitutions
+/// needed to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+	let b: Vec<char> = b.chars().collect();
+	let mut row: Vec<usize> = (0..=b.len()).collect();
+	for (i, ca) in a.chars().enumerate() {
+		let mut diagonal = row[0];
+		row[0] = i + 1;
+		for (j, &cb) in b.iter().enumerate() {
+			let substitution = diagonal + if ca == cb { 0 } else { 1 };
+			diagonal = row[j + 1];
+			row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+		}
+	}
+	row[b.len()]
+}
+
+/// The candidate most similar to `name`, if any is similar enough to be a typo of it.
+pub fn suggest<'a>(name: &str, candidates: &[&'a str]) -> Option<&'a str> {
+	candidates
+		.iter()
+		.map(|&c| (edit_distance(name, c), c))
+		.filter(|&(d, c)| d <= (c.chars().count() / 2).max(1))
+		.min()
+		.map(|(_, c)| c)
+}
+
 #[derive(Debug, Display, EError, From)]
 pub enum GetError {
 	#[display(fmt = "Could not get laspad.toml")]
@@ -158,20 +395,24 @@ fn parse_mod_settings(path: &Path) -> Result<Option<Config>, LuaGetError> {
 
 	let conf = fs::read_to_string(path.join("mod.settings"));
 	let conf = match conf.as_ref() {
-		Ok(s) => static_lua::parse(s).map_err(|_| Parse)?,
+		Ok(s) => static_lua::parse(s)?,
 		Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
 		Err(_) => return Err(conf.unwrap_err().into()),
 	};
 
 	let mut tags = Vec::new();
 
-	for (k, &v) in &conf {
+	for (k, v) in &conf {
 		if k.starts_with("tag_") {
-			tags.push(v.into());
+			let tag = v.as_str().ok_or_else(|| NotAString(k.to_string(), v.type_name()))?;
+			tags.push(tag.into());
 		}
 	}
 
-	let get = move |key: &'static str| conf.get(key).map(|s| *s).ok_or(MissingKey(key));
+	let get = |key: &'static str| match conf.get(key) {
+		Some(v) => v.as_str().ok_or_else(|| NotAString(key.into(), v.type_name())),
+		None => Err(MissingKey(key)),
+	};
 
 	let branch = Branch {
 		name: get("name")?.into(),
@@ -182,6 +423,15 @@ fn parse_mod_settings(path: &Path) -> Result<Option<Config>, LuaGetError> {
 		preview: Some(get("image")?.into()),
 		website: None,
 		item: Some(get("publish_id")?.parse().map_err(|_| InvalidPublishId)?),
+		visibility: None,
+		changelog: None,
+		guard: None,
+		app_id: None,
+		screenshots: None,
+		videos: None,
+		dependencies: None,
+		remove_dependencies: None,
+		overlays: None,
 	};
 
 	let mut branches = HashMap::new();
@@ -191,12 +441,46 @@ fn parse_mod_settings(path: &Path) -> Result<Option<Config>, LuaGetError> {
 		source_output_dir: Some((get("source_dir")?.into(), get("output_dir")?.into())),
 		deps: Vec::new(),
 		branches,
+		app_id: None,
 	}))
 }
 
 impl Config {
+	/// The latest version of `laspad.toml`, which `laspad migrate` converts to
+	pub const VERSION: i64 = 2;
 	pub const EXAMPLE: Option<&'static str> = Some(include_str!("../assets/laspad.toml"));
 
+	/// The names of all branches, sorted.
+	pub fn branch_names(&self) -> Vec<&str> {
+		let mut names: Vec<_> = self.branches.keys().map(|s| s.as_str()).collect();
+		names.sort();
+		names
+	}
+
+	/// The dependencies of the project and those every branch adds, to download them all.
+	pub fn all_deps(&self) -> Vec<Dep> {
+		let mut deps = self.deps.clone();
+		for branch in self.branches.values() {
+			for dep in branch.dependencies.iter().flatten() {
+				if !deps.contains(dep) {
+					deps.push(dep.clone());
+				}
+			}
+		}
+		deps
+	}
+
+	pub fn branch(&self, name: &str) -> Result<&Branch, UnknownBranch> {
+		self.branches.get(name).ok_or_else(|| {
+			let available = self.branch_names();
+			UnknownBranch {
+				name:       name.into(),
+				suggestion: suggest(name, &available).map(String::from),
+				available:  available.into_iter().map(String::from).collect(),
+			}
+		})
+	}
+
 	pub fn get(path: &Path) -> Result<Option<Self>, GetError> {
 		match fs::read_to_string(path.join("laspad.toml")) {
 			Ok(s) => Ok(Some(s.parse().map_err(TOMLGetError::ParseError)?)),
@@ -222,6 +506,7 @@ impl FromStr for Config {
 		let c = match version {
 			None | Some(0) => Config {
 				source_output_dir: None,
+				app_id:            None,
 				deps:              Vec::new(),
 				branches:          c
 					.into_iter()
@@ -231,18 +516,169 @@ impl FromStr for Config {
 					})
 					.collect::<Result<_, _>>()?,
 			},
-			Some(1) => Config {
-				source_output_dir: None,
+			Some(v @ 1) | Some(v @ 2) => Config {
+				source_output_dir: {
+					let mut dir = |key: &str| -> Result<Option<PathBuf>, toml::de::Error> {
+						c.remove(key).map_or(Ok(None), |d| d.try_into().map(Some))
+					};
+					match (dir("source_dir")?, dir("output_dir")?) {
+						(Some(source), Some(output)) => Some((source, output)),
+						(Some(source), None) => Some((source.clone(), source)),
+						(None, Some(output)) => Some((Project::SOURCE_PATH.into(), output)),
+						(None, None) => None,
+					}
+				},
+				app_id:            c
+					.remove("app_id")
+					.map_or(Ok(None), |a| a.try_into().map(Some))?,
 				deps:              c
 					.remove("dependencies")
 					.map_or(Ok(Vec::new()), |d| d.try_into())?,
-				branches:          c
-					.remove("branch")
-					.ok_or(ExpectedKey("branch", "Table"))?
-					.try_into()?,
+				branches:          if v == 1 {
+					c.remove("branch")
+						.ok_or(ExpectedKey("branch", "Table"))?
+						.try_into()?
+				} else {
+					let defaults = match c.remove("defaults") {
+						Some(toml::Value::Table(defaults)) => defaults,
+						None => Table::new(),
+						Some(_) => return Err(ExpectedKey("defaults", "Table")),
+					};
+					let branches = match c.remove("branch") {
+						Some(toml::Value::Table(branches)) => branches,
+						_ => return Err(ExpectedKey("branch", "Table")),
+					};
+					branches
+						.keys()
+						.map(|name| {
+							let branch = inherit(&defaults, &branches, name)?.try_into()?;
+							Ok((name.clone(), branch))
+						})
+						.collect::<Result<_, ParseError>>()?
+				},
 			},
 			Some(v) => return Err(InvalidVersion(v)),
 		};
 		Ok(c)
 	}
 }
+
+#[cfg(test)]
+#[test]
+fn test() {
+	let config: Config = r#"
+version = 1
+[branch.master]
+name = "Test"
+tags = []
+[branch.beta]
+name = "Test beta"
+tags = []
+"#
+	.parse()
+	.unwrap();
+	assert_eq!(edit_distance("mater", "master"), 1);
+	assert!(config.branch("beta").is_ok());
+	let unknown = config.branch("mastr").err().unwrap();
+	assert_eq!(unknown.suggestion.as_ref().unwrap(), "master");
+	assert_eq!(unknown.available, vec!["beta", "master"]);
+	assert!(config.branch("release").err().unwrap().suggestion.is_none());
+
+	let config: Config = r#"
+version = 2
+[defaults]
+tags    = ["Map"]
+website = "https://example.com"
+item    = "2B"
+[defaults.guard]
+dirty = "fail"
+[branch.master]
+name = "Test"
+[branch.master.guard]
+tag = true
+[branch.beta]
+extends = "master"
+name    = "Test beta"
+item    = "1A"
+[branch.test]
+extends = "beta"
+tags    = []
+"#
+	.parse()
+	.unwrap();
+	let test = config.branch("test").unwrap();
+	assert_eq!(test.name, "Test beta");
+	assert!(test.tags.is_empty());
+	assert!(test.item.is_none());
+	assert_eq!(
+		config.branch("beta").unwrap().item,
+		Some("1A".parse().unwrap())
+	);
+	assert_eq!(test.website.as_ref().unwrap(), "https://example.com");
+	let guard = test.guard.as_ref().unwrap();
+	assert_eq!(guard.tag, Some(true));
+	assert_eq!(guard.dirty, Some(crate::guard::Action::Fail));
+	assert!(config.branch("master").unwrap().item.is_none());
+
+	let unknown = "version = 2\n[branch.beta]\nextends = \"master\"\nname = \"a\"\ntags = []"
+		.parse::<Config>();
+	match unknown {
+		Err(ParseError::UnknownParent { parent, .. }) => assert_eq!(parent, "master"),
+		_ => panic!("Expected an unknown parent"),
+	}
+	let cycle = r#"
+version = 2
+[branch.a]
+extends = "b"
+[branch.b]
+extends = "a"
+"#
+	.parse::<Config>();
+	match cycle {
+		Err(ParseError::InheritanceCycle(cycle)) => assert_eq!(cycle, "a -> b -> a"),
+		_ => panic!("Expected an inheritance cycle"),
+	}
+
+	let config: Config = r#"
+version = 2
+dependencies = [
+	"1A",
+	{ git = "https://example.com/lib.git", rev = "v1", subdir = "mod" },
+	{ path = "../shared" },
+]
+[branch.master]
+name = "Test"
+tags = []
+"#
+	.parse()
+	.unwrap();
+	assert_eq!(
+		config.deps,
+		vec![
+			Dep::Workshop("1A".parse().unwrap()),
+			Dep::Git {
+				git:    "https://example.com/lib.git".into(),
+				rev:    Some("v1".into()),
+				subdir: Some("mod".into()),
+			},
+			Dep::Path {
+				path: "../shared".into(),
+			},
+		]
+	);
+	assert!(config.source_output_dir.is_none());
+
+	let config: Config = r#"
+version    = 2
+source_dir = "source"
+[branch.master]
+name = "Test"
+tags = []
+"#
+	.parse()
+	.unwrap();
+	assert_eq!(
+		config.source_output_dir,
+		Some((PathBuf::from("source"), PathBuf::from("source")))
+	);
+}
@@ -0,0 +1,355 @@
+use derive_more::{Display, From};
+use erroneous::Error as EError;
+use std::{fs, io, path::PathBuf};
+
+use crate::{
+	config::{self, Config, Dep},
+	download,
+	item::Item,
+	project::Project,
+	scan::{self, code_end, strings, Kind},
+	util,
+};
+
+#[derive(Debug, Display, EError, From)]
+pub enum Error {
+	#[display(fmt = "{}", _0)]
+	ReadError(#[error(defer)] util::ReadError),
+	#[display(fmt = "Could not write '{}'", "_0.display()")]
+	Write(PathBuf, #[error(source)] io::Error),
+	#[display(fmt = "Could not remove '{}'", "_0.display()")]
+	Remove(PathBuf, #[error(source)] io::Error),
+	#[display(fmt = "The project has no laspad.toml to add dependencies to")]
+	NoConfig,
+	#[display(fmt = "Could not parse laspad.toml")]
+	Parse(#[error(source)] toml::de::Error),
+	#[display(fmt = "Could not parse laspad.toml")]
+	Config(#[error(source)] config::ParseError),
+	#[display(fmt = "Dependencies need laspad.toml version 1 or later")]
+	OldVersion,
+	#[display(fmt = "The `dependencies` array of laspad.toml is not closed")]
+	Unclosed,
+	#[display(fmt = "Could not find workshop item {}", _0)]
+	Info(Item, #[error(source)] download::Error),
+	#[display(fmt = "The workshop item {} is not a dependency of the project", _0)]
+	NotFound(Item),
+}
+
+/// The `dependencies` arrays of laspad.toml, at the top level, in `[defaults]` and in branches,
+/// as the table they are in, the line of their key and the line they are closed on.
+fn find_arrays(lines: &[String]) -> Result<Vec<(Vec<String>, usize, usize)>, Error> {
+	let scanned = scan::lines(lines);
+	let mut arrays = Vec::new();
+	for (key, line) in scanned.iter().enumerate() {
+		let table = match line.path.split_last() {
+			Some((last, table)) if line.kind == Kind::Key && last == "dependencies" => table,
+			_ => continue,
+		};
+		let known = match table.len() {
+			0 => true,
+			1 => table[0] == "defaults",
+			2 => table[0] == "branch",
+			_ => false,
+		};
+		if !known {
+			continue;
+		}
+		let end = scanned[key..]
+			.iter()
+			.position(|l| !l.open)
+			.ok_or(Error::Unclosed)?;
+		arrays.push((table.to_vec(), key, key + end));
+	}
+	Ok(arrays)
+}
+
+/// The line of the top level `dependencies` key and the line its array is closed on.
+fn find_array(lines: &[String]) -> Result<Option<(usize, usize)>, Error> {
+	Ok(find_arrays(lines)?
+		.into_iter()
+		.find(|(table, ..)| table.is_empty())
+		.map(|(_, key, end)| (key, end)))
+}
+
+/// Adds the items with their titles as comments to the top level `dependencies` array,
+/// creating it if needed, and leaves the rest of `content` as it is.
+pub fn add_to(content: &str, items: &[(Item, String)]) -> Result<String, Error> {
+	let mut lines: Vec<String> = content.lines().map(String::from).collect();
+	let entries = items
+		.iter()
+		.map(|(item, title)| format!("\t\"{:X}\", # {}", item, title));
+
+	match find_array(&lines)? {
+		Some((key, end)) if key == end => {
+			// Spread the array over several lines to make room for the comments
+			let line = &lines[key];
+			let open = line.find('[').expect("Could not find '[' in line") + 1;
+			let close = line[..code_end(line)]
+				.rfind(']')
+				.expect("Could not find ']' in line");
+			let inner = line[open..close].trim().trim_end_matches(',');
+			let mut new = vec![line[..open].to_owned()];
+			if !inner.is_empty() {
+				new.push(format!("\t{},", inner));
+			}
+			new.extend(entries);
+			new.push(line[close..].to_owned());
+			lines.splice(key..=key, new);
+		},
+		Some((key, end)) => {
+			// The last element needs a comma before another one can follow it
+			let last = (key + 1..end)
+				.rev()
+				.find(|&i| !lines[i][..code_end(&lines[i])].trim().is_empty());
+			if let Some(last) = last {
+				let line = &lines[last];
+				let code = line[..code_end(line)].trim_end();
+				if !code.ends_with(',') {
+					lines[last] = format!("{},{}", code, &line[code.len()..]);
+				}
+			}
+			lines.splice(end..end, entries);
+		},
+		None => {
+			let at = scan::lines(&lines)
+				.iter()
+				.position(|l| l.is(Kind::Key, &["version"]))
+				.map_or(0, |i| i + 1);
+			let mut new = vec!["dependencies = [".to_owned()];
+			new.extend(entries);
+			new.push("]".into());
+			lines.splice(at..at, new);
+		},
+	}
+
+	Ok(scan::join(&lines, content))
+}
+
+/// Removes the items from the top level `dependencies` array, or else from the arrays of the
+/// branches and defaults that have them, along with the comments of the lines they were alone on,
+/// and leaves the rest of `content` as it is.
+pub fn remove_from(content: &str, items: &[Item]) -> Result<String, Error> {
+	let mut lines: Vec<String> = content.lines().map(String::from).collect();
+
+	for &item in items {
+		let mut found = Vec::new();
+		for (table, key, end) in find_arrays(&lines)? {
+			let string = (key..=end).find_map(|i| {
+				strings(&lines[i])
+					.into_iter()
+					.find(|&(s, e)| lines[i][s..e].parse::<Item>().ok() == Some(item))
+					.map(|s| (i, s))
+			});
+			if let Some(string) = string {
+				found.push((table, key, string));
+			}
+		}
+		// Branches keep what they add to the project's dependencies
+		if found.iter().any(|(table, ..)| table.is_empty()) {
+			found.retain(|(table, ..)| table.is_empty());
+		}
+		if found.is_empty() {
+			return Err(Error::NotFound(item));
+		}
+
+		// From the bottom up, so that the lines of the arrays above stay where they are
+		for (table, key, (i, (start, stop))) in found.into_iter().rev() {
+			if !table.is_empty() {
+				info!(
+					"Removing {} from the dependencies of [{}]",
+					item,
+					table.join(".")
+				);
+			}
+			let line = &lines[i];
+			let rest = &line[stop + 1..];
+			let comma = rest.len() - rest.trim_start().trim_start_matches(',').len();
+			let removed = format!("{}{}", &line[..start - 1], &rest[comma..].trim_start());
+			if i != key && removed[..code_end(&removed)].trim().is_empty() {
+				lines.remove(i);
+			} else {
+				lines[i] = removed;
+			}
+		}
+	}
+
+	Ok(scan::join(&lines, content))
+}
+
+fn config(project: &Project) -> Result<(PathBuf, String), Error> {
+	let path = project.path.join("laspad.toml");
+	if !path.exists() {
+		return Err(Error::NoConfig);
+	}
+	let content = util::read_to_string(&path)?;
+	let value: toml::Value = content.parse()?;
+	match value.get("version").and_then(|v| v.as_integer()) {
+		None | Some(0) => Err(Error::OldVersion),
+		Some(_) => Ok((path, content)),
+	}
+}
+
+/// Adds the workshop items to the dependencies in `laspad.toml`.
+/// Returns the items that weren't dependencies yet, with their titles.
+pub fn add(project: &Project, items: &[Item]) -> Result<Vec<(Item, String)>, Error> {
+	let (path, content) = config(project)?;
+	let mut added: Vec<(Item, String)> = Vec::new();
+	for &item in items {
+		let present = project.config.deps.contains(&Dep::Workshop(item));
+		if present || added.iter().any(|a| a.0 == item) {
+			info!("{} is already a dependency", item);
+			continue;
+		}
+		// Also makes sure that the item exists
+		let info = download::get_info(item).map_err(|e| Error::Info(item, e))?;
+		let title = info.title.unwrap_or_else(|| format!("{:X}", item));
+		added.push((item, title));
+	}
+
+	if !added.is_empty() {
+		let content = add_to(&content, &added)?;
+		fs::write(&path, content).map_err(|e| Error::Write(path, e))?;
+	}
+	Ok(added)
+}
+
+/// Removes the workshop items from the dependencies in `laspad.toml`
+/// and deletes their downloads, unless a branch still depends on them.
+pub fn remove(project: &Project, items: &[Item]) -> Result<(), Error> {
+	let (path, content) = config(project)?;
+	let content = remove_from(&content, items)?;
+	let deps = content.parse::<Config>()?.all_deps();
+	fs::write(&path, content).map_err(|e| Error::Write(path, e))?;
+
+	for &item in items {
+		if deps.contains(&Dep::Workshop(item)) {
+			info!(
+				"Keeping the download of {}, a branch still depends on it",
+				item
+			);
+			continue;
+		}
+		let path = project.path_for_item(item);
+		if path.exists() {
+			fs::remove_dir_all(&path).map_err(|e| Error::Remove(path, e))?;
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+	let item = |s: &str| s.parse::<Item>().unwrap();
+	let config = r#"version = 2
+
+# These mods are included in your mod
+dependencies = [
+	"https://steamcommunity.com/sharedfiles/filedetails/?id=1674847865", # BetterMarineMinimap
+	"637CEBDD" # Skill Tier Badges
+	# { path = "../shared-lib" },
+]
+
+[branch.master]
+name = "Test" # the title
+"#;
+
+	let added = add_to(config, &[(item("1A"), "Some mod".into())]).unwrap();
+	assert_eq!(
+		added,
+		r#"version = 2
+
+# These mods are included in your mod
+dependencies = [
+	"https://steamcommunity.com/sharedfiles/filedetails/?id=1674847865", # BetterMarineMinimap
+	"637CEBDD", # Skill Tier Badges
+	# { path = "../shared-lib" },
+	"1A", # Some mod
+]
+
+[branch.master]
+name = "Test" # the title
+"#
+	);
+	let removed = remove_from(&added, &[item("63D42679"), item("1A")]).unwrap();
+	assert_eq!(
+		removed,
+		r#"version = 2
+
+# These mods are included in your mod
+dependencies = [
+	"637CEBDD", # Skill Tier Badges
+	# { path = "../shared-lib" },
+]
+
+[branch.master]
+name = "Test" # the title
+"#
+	);
+	assert!(remove_from(&removed, &[item("1A")]).is_err());
+
+	assert_eq!(
+		add_to(
+			"version = 1\n[branch.master]\n",
+			&[(item("1A"), "A".into())]
+		)
+		.unwrap(),
+		"version = 1\ndependencies = [\n\t\"1A\", # A\n]\n[branch.master]\n"
+	);
+	let inline = "version = 1\ndependencies = [\"1B\", { path = \"lib\" }]\n";
+	let added = add_to(inline, &[(item("1A"), "A".into())]).unwrap();
+	assert_eq!(
+		added,
+		"version = 1\ndependencies = [\n\t\"1B\", { path = \"lib\" },\n\t\"1A\", # A\n]\n"
+	);
+	assert_eq!(
+		remove_from(inline, &[item("1B")]).unwrap(),
+		"version = 1\ndependencies = [{ path = \"lib\" }]\n"
+	);
+
+	let branches = "version = 2\n[defaults]\ndependencies = [\"1A\"]\n\
+		 [branch.a]\ndependencies = [\n\t\"1A\", # A\n\t\"1B\",\n]\n";
+	assert_eq!(
+		remove_from(branches, &[item("1A")]).unwrap(),
+		"version = 2\n[defaults]\ndependencies = []\n[branch.a]\ndependencies = [\n\t\"1B\",\n]\n"
+	);
+
+	let crlf = |s: &str| s.replace('\n', "\r\n");
+	let added = add_to(&crlf(inline), &[(item("1A"), "A".into())]).unwrap();
+	assert_eq!(
+		added,
+		crlf("version = 1\ndependencies = [\n\t\"1B\", { path = \"lib\" },\n\t\"1A\", # A\n]\n")
+	);
+	assert_eq!(
+		remove_from(&added, &[item("1A")]).unwrap(),
+		crlf("version = 1\ndependencies = [\n\t\"1B\", { path = \"lib\" },\n]\n")
+	);
+
+	let root = crate::util::TempDir::new("deps");
+	let project = Project::write(
+		&root,
+		"version = 1\ndependencies = [\"1A\", \"1B\"]\n\
+		 [branch.master]\nname = \"Test\"\ntags = []\ndependencies = [\"1A\"]\n",
+	);
+	for &item in &[item("1A"), item("1B")] {
+		fs::create_dir_all(project.path_for_item(item)).unwrap();
+	}
+	remove(&project, &[item("1A"), item("1B")]).unwrap();
+	assert!(project.path_for_item(item("1A")).exists());
+	assert!(!project.path_for_item(item("1B")).exists());
+	assert_eq!(
+		fs::read_to_string(root.join("laspad.toml")).unwrap(),
+		"version = 1\ndependencies = []\n\
+		 [branch.master]\nname = \"Test\"\ntags = []\ndependencies = [\"1A\"]\n"
+	);
+
+	// Once only the branch has it, it is removed from the branch
+	remove(&project, &[item("1A")]).unwrap();
+	assert!(!project.path_for_item(item("1A")).exists());
+	assert_eq!(
+		fs::read_to_string(root.join("laspad.toml")).unwrap(),
+		"version = 1\ndependencies = []\n\
+		 [branch.master]\nname = \"Test\"\ntags = []\ndependencies = []\n"
+	);
+	assert!(remove(&project, &[item("1A")]).is_err());
+}
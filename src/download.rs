@@ -6,7 +6,7 @@ use erroneous::Error as EError;
 use serde_derive::Deserialize;
 use std::{
 	fs::{self, File},
-	io::{self, Cursor, Read, Write},
+	io::{self, Cursor, Read, Seek, Write},
 	path::{Path, PathBuf},
 };
 use zip::ZipArchive;
@@ -52,8 +52,13 @@ pub enum Error {
 
 #[derive(Deserialize, Debug)]
 pub struct PublishedFile {
-	pub file_url:     Box<str>,
-	pub time_updated: u64,
+	pub file_url:        Box<str>,
+	pub time_updated:    u64,
+	/// The app ID of the game the item belongs to
+	#[serde(default)]
+	pub consumer_app_id: Option<u32>,
+	#[serde(default)]
+	pub title:           Option<String>,
 }
 
 pub fn get_info(item: Item) -> Result<PublishedFile, Error> {
@@ -75,6 +80,11 @@ pub fn get_info(item: Item) -> Result<PublishedFile, Error> {
 	Ok(root.publishedfiledetails.publishedfile)
 }
 
+pub fn fetch(item: Item) -> Result<Vec<u8>, Error> {
+	let info = get_info(item)?;
+	get(&info.file_url).map_err(Error::ZipLoad)
+}
+
 fn extract(url: &str, path: &Path) -> Result<(), Error> {
 	if path.exists() {
 		fs::remove_dir_all(&path).map_err(Error::RemoveTarget)?;
@@ -83,7 +93,12 @@ fn extract(url: &str, path: &Path) -> Result<(), Error> {
 	}
 
 	let buf = get(url).map_err(Error::ZipLoad)?;
-	let mut archive = ZipArchive::new(Cursor::new(buf))?;
+	unzip(Cursor::new(buf), path)
+}
+
+/// Extracts a Zip archive into `path`.
+pub fn unzip(r: impl Read + Seek, path: &Path) -> Result<(), Error> {
+	let mut archive = ZipArchive::new(r)?;
 	for i in 0..archive.len() {
 		let mut file = archive.by_index(i)?;
 		let file_path = path.join(file.name());
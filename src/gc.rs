@@ -0,0 +1,217 @@
+use derive_more::{Display, From};
+use erroneous::Error as EError;
+use std::{
+	collections::HashMap,
+	fmt,
+	fs,
+	io,
+	path::{Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+use crate::{
+	config::Dep,
+	item::Item,
+	lock::{self, Lock},
+	project::Project,
+};
+
+#[derive(Debug, Display, EError, From)]
+pub enum Error {
+	#[display(fmt = "Could not list '{}'", "_0.display()")]
+	List(PathBuf, #[error(source)] io::Error),
+	#[display(fmt = "Could not remove '{}'", "_0.display()")]
+	Remove(PathBuf, #[error(source)] io::Error),
+	#[display(fmt = "Could not read laspad.lock")]
+	Lock(#[error(source)] lock::Error),
+}
+
+/// What a garbage collection removed.
+#[derive(Default)]
+pub struct Report {
+	pub removed: Vec<PathBuf>,
+	/// The size of the removed files, in bytes
+	pub size:    u64,
+}
+
+impl fmt::Display for Report {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		for path in &self.removed {
+			writeln!(f, "Removed {}", path.display())?;
+		}
+		let size = self.size as f64;
+		write!(f, "Reclaimed ")?;
+		if size >= 1024.0 * 1024.0 * 1024.0 {
+			write!(f, "{:.1} GiB", size / (1024.0 * 1024.0 * 1024.0))
+		} else if size >= 1024.0 * 1024.0 {
+			write!(f, "{:.1} MiB", size / (1024.0 * 1024.0))
+		} else {
+			write!(f, "{:.1} KiB", size / 1024.0)
+		}
+	}
+}
+
+impl Report {
+	fn remove(&mut self, path: PathBuf) -> Result<(), Error> {
+		// Files that can't be read anymore are about to be removed anyway
+		self.size += WalkDir::new(&path)
+			.into_iter()
+			.filter_map(|e| e.ok())
+			.filter_map(|e| e.metadata().ok())
+			.filter(|m| m.is_file())
+			.map(|m| m.len())
+			.sum::<u64>();
+		fs::remove_dir_all(&path).map_err(|e| Error::Remove(path.clone(), e))?;
+		self.removed.push(path);
+		Ok(())
+	}
+}
+
+fn subdirs(path: &Path) -> Result<Vec<PathBuf>, Error> {
+	if !path.exists() {
+		return Ok(Vec::new());
+	}
+	let mut dirs = Vec::new();
+	for entry in fs::read_dir(path).map_err(|e| Error::List(path.into(), e))? {
+		let path = entry.map_err(|e| Error::List(path.into(), e))?.path();
+		if path.is_dir() {
+			dirs.push(path);
+		}
+	}
+	Ok(dirs)
+}
+
+/// Removes the downloads of workshop dependencies and the clones of git dependencies
+/// that neither the configuration nor the lockfile refers to anymore.
+pub fn dependencies(project: &Project) -> Result<Report, Error> {
+	let mut report = Report::default();
+	let deps = project.config.all_deps();
+
+	let steam = project.path.join(Project::DEPENDENCIES_STEAM_PATH);
+	for dir in subdirs(&steam)? {
+		let item = dir
+			.file_name()
+			.and_then(|n| n.to_str())
+			.and_then(|n| n.parse::<Item>().ok());
+		if !item.map_or(false, |i| deps.contains(&Dep::Workshop(i))) {
+			report.remove(dir)?;
+		}
+	}
+
+	let lock = Lock::read(project)?;
+	let clones: Vec<PathBuf> = deps
+		.iter()
+		.filter_map(|d| match d {
+			Dep::Git { git, .. } => Some(git.as_str()),
+			_ => None,
+		})
+		.chain(lock.dependencies.iter().map(|l| l.git.as_str()))
+		.map(|git| project.path_for_git(git))
+		.collect();
+	// The clones are in `<hash>/<name>`
+	let git = project.path.join(Project::DEPENDENCIES_GIT_PATH);
+	for hash in subdirs(&git)? {
+		let mut kept = 0;
+		for dir in subdirs(&hash)? {
+			if clones.contains(&dir) {
+				kept += 1;
+			} else {
+				report.remove(dir)?;
+			}
+		}
+		if kept == 0 {
+			report.remove(hash)?;
+		}
+	}
+
+	Ok(report)
+}
+
+/// Removes every version of the items installed into `path`, see `download::install`,
+/// but the newest one.
+pub fn installs(path: &Path) -> Result<Report, Error> {
+	let mut report = Report::default();
+	let mut newest: HashMap<u64, (u64, PathBuf)> = HashMap::new();
+	for dir in subdirs(path)? {
+		// `m<hexadecimal ID>_<timestamp>`
+		let version = dir
+			.file_name()
+			.and_then(|n| n.to_str())
+			.filter(|n| n.starts_with('m'))
+			.and_then(|n| {
+				let mut parts = n[1..].splitn(2, '_');
+				let item = u64::from_str_radix(parts.next()?, 16).ok()?;
+				let time = parts.next()?.parse::<u64>().ok()?;
+				Some((item, time))
+			});
+		let (item, time) = match version {
+			Some(version) => version,
+			None => continue,
+		};
+		match newest.remove(&item) {
+			Some((newest_time, newest_dir)) if newest_time > time => {
+				newest.insert(item, (newest_time, newest_dir));
+				report.remove(dir)?;
+			},
+			Some((_, older)) => {
+				newest.insert(item, (time, dir));
+				report.remove(older)?;
+			},
+			None => {
+				newest.insert(item, (time, dir));
+			},
+		}
+	}
+	Ok(report)
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+	let root = crate::util::TempDir::new("gc");
+	let steam = root.join(Project::DEPENDENCIES_STEAM_PATH);
+	for dir in &["1A", "2B", "not-an-item"] {
+		fs::create_dir_all(steam.join(dir)).unwrap();
+	}
+	fs::write(steam.join("2B/file.lua"), vec![0u8; 2048]).unwrap();
+	let project = Project::write(
+		&root,
+		r#"version = 2
+dependencies = ["1A", { git = "https://example.com/kept.git" }]
+[branch.master]
+name         = "Test"
+tags         = []
+[branch.beta]
+name         = "Test beta"
+tags         = []
+dependencies = [{ git = "https://example.com/beta.git" }]
+"#,
+	);
+	let kept = project.path_for_git("https://example.com/kept.git");
+	let beta = project.path_for_git("https://example.com/beta.git");
+	let stale = project.path_for_git("https://example.com/stale.git");
+	for dir in &[&kept, &beta, &stale] {
+		fs::create_dir_all(dir).unwrap();
+	}
+
+	let report = dependencies(&project).unwrap();
+	assert_eq!(report.size, 2048);
+	assert_eq!(report.removed.len(), 4);
+	assert!(steam.join("1A").exists());
+	assert!(!steam.join("2B").exists());
+	assert!(!steam.join("not-an-item").exists());
+	assert!(kept.exists() && beta.exists());
+	assert!(!stale.parent().unwrap().exists());
+	assert_eq!(report.to_string().lines().last().unwrap(), "Reclaimed 2.0 KiB");
+
+	let dir = root.join("installs");
+	for version in &["m1a_100", "m1a_300", "m1a_200", "m2b_100", "other"] {
+		fs::create_dir_all(dir.join(version)).unwrap();
+	}
+	let mut removed = installs(&dir).unwrap().removed;
+	removed.sort();
+	assert_eq!(removed, vec![dir.join("m1a_100"), dir.join("m1a_200")]);
+	assert!(dir.join("m1a_300").exists());
+	assert!(dir.join("m2b_100").exists());
+	assert!(dir.join("other").exists());
+}
@@ -0,0 +1,237 @@
+use derive_more::{Display, From};
+use erroneous::Error as EError;
+use git2::{BranchType, ObjectType, Oid, Repository, StatusOptions};
+use serde_derive::Deserialize;
+
+use crate::{check::Problem, config::Branch, lock::Lock, project::Project};
+
+#[derive(Debug, Display, EError, From)]
+pub enum Error {
+	#[display(fmt = "Could not inspect the git repository")]
+	Git(#[error(source)] git2::Error),
+	#[display(fmt = "Could not create the git tag '{}'", _0)]
+	Tag(String, #[error(source)] git2::Error),
+}
+
+/// What to do when a guard finds a problem.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+	Fail,
+	Warn,
+	Ignore,
+}
+
+/// Checks of the git repository before publishing a branch, in `[branch.<name>.guard]`.
+#[derive(Default, Deserialize)]
+pub struct Guard {
+	/// When the working tree has uncommitted changes, warns by default
+	pub dirty:      Option<Action>,
+	/// When HEAD hasn't been pushed to its upstream, warns by default
+	pub unpushed:   Option<Action>,
+	/// The git branch HEAD has to be on
+	pub git_branch: Option<String>,
+	/// Whether to tag the published commit as `workshop/<branch>/<n>`
+	pub tag:        Option<bool>,
+}
+
+/// The keys of a guard table
+pub const GUARD_KEYS: &[&str] = &["dirty", "unpushed", "git_branch", "tag"];
+
+/// Files laspad writes itself, which don't make the tree dirty.
+fn is_laspad_file(path: &str) -> bool {
+	// Untracked directories are reported as a whole, like `compiled/`
+	let first = path.split('/').next().unwrap_or(path);
+	first.starts_with(".published.")
+		|| first.starts_with(".modid.")
+		|| first == Lock::PATH
+		|| first == "steam_appid.txt"
+		|| Project::GENERATED_PATHS.contains(&first)
+}
+
+/// Checks the git repository of the project against the guard of the branch.
+/// Projects that aren't git repositories pass every check.
+pub fn check(
+	project: &Project,
+	branch: &Branch,
+	branch_name: &str,
+) -> Result<Vec<(Action, Problem)>, Error> {
+	let default = Guard::default();
+	let guard = branch.guard.as_ref().unwrap_or(&default);
+	let mut problems = Vec::new();
+	let repo = match Repository::open(&project.path) {
+		Ok(repo) => repo,
+		Err(_) => return Ok(problems),
+	};
+	// Without commits, there is no HEAD
+	let head = repo.head().ok();
+	let name = head
+		.as_ref()
+		.filter(|h| h.is_branch())
+		.and_then(|h| h.shorthand())
+		.map(String::from);
+	let mut problem = |action: Action, field: &str, message: String| {
+		if action != Action::Ignore {
+			problems.push((
+				action,
+				Problem {
+					field:    format!("branch.{}.guard.{}", branch_name, field),
+					message,
+					location: None,
+				},
+			))
+		}
+	};
+
+	let dirty = guard.dirty.unwrap_or(Action::Warn);
+	if dirty != Action::Ignore {
+		let statuses = repo.statuses(Some(
+			StatusOptions::new()
+				.include_untracked(true)
+				.include_ignored(false),
+		))?;
+		let changed: Vec<_> = statuses
+			.iter()
+			.filter_map(|s| s.path().map(String::from))
+			.filter(|p| !is_laspad_file(p))
+			.collect();
+		if !changed.is_empty() {
+			problem(
+				dirty,
+				"dirty",
+				format!(
+					"The working tree has {} uncommitted changes, e.g. '{}'",
+					changed.len(),
+					changed[0]
+				),
+			);
+		}
+	}
+
+	if let Some(expected) = &guard.git_branch {
+		if name.as_ref() != Some(expected) {
+			problem(
+				Action::Fail,
+				"git_branch",
+				format!(
+					"HEAD is on {}, but the branch is published from '{}'",
+					name.as_ref()
+						.map_or("no branch".into(), |n| format!("'{}'", n)),
+					expected
+				),
+			);
+		}
+	}
+
+	let unpushed = guard.unpushed.unwrap_or(Action::Warn);
+	let oid = head.as_ref().and_then(|h| h.target());
+	if let (Some(name), Some(oid), true) = (&name, oid, unpushed != Action::Ignore) {
+		let upstream = repo
+			.find_branch(name, BranchType::Local)
+			.and_then(|b| b.upstream())
+			.ok();
+		match upstream.as_ref().and_then(|u| u.get().target()) {
+			Some(remote) if remote == oid || repo.graph_descendant_of(remote, oid)? => (),
+			Some(_) => problem(
+				unpushed,
+				"unpushed",
+				format!(
+					"HEAD has not been pushed to '{}'",
+					upstream
+						.as_ref()
+						.and_then(|u| u.name().ok())
+						.and_then(|n| n)
+						.unwrap_or("its upstream")
+				),
+			),
+			None => problem(
+				unpushed,
+				"unpushed",
+				format!("The git branch '{}' has no upstream to push to", name),
+			),
+		}
+	}
+
+	Ok(problems)
+}
+
+/// Tags `commit` as `workshop/<branch>/<n>`, `n` being one more than that of the last such tag,
+/// if the guard of the branch asks for it.
+pub fn tag(
+	project: &Project,
+	branch: &Branch,
+	branch_name: &str,
+	commit: Oid,
+) -> Result<Option<String>, Error> {
+	if !branch.guard.as_ref().and_then(|g| g.tag).unwrap_or(false) {
+		return Ok(None);
+	}
+
+	let repo = Repository::open(&project.path)?;
+	let prefix = format!("workshop/{}/", branch_name);
+	let tags = repo.tag_names(Some(&format!("{}*", prefix)))?;
+	let n = tags
+		.iter()
+		.filter_map(|t| t.and_then(|t| t[prefix.len()..].parse::<u32>().ok()))
+		.max()
+		.unwrap_or(0) + 1;
+	let name = format!("{}{}", prefix, n);
+	let object = repo.find_object(commit, Some(ObjectType::Commit))?;
+	repo.tag_lightweight(&name, &object, false)
+		.map_err(|e| Error::Tag(name.clone(), e))?;
+	Ok(Some(name))
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+	use std::fs;
+
+	let root = crate::util::TempDir::new("guard");
+	fs::create_dir_all(root.join("src")).unwrap();
+	let project = Project::write(
+		&root,
+		r#"
+version = 1
+[branch.master]
+name = "Test"
+tags = []
+[branch.master.guard]
+dirty      = "fail"
+unpushed   = "ignore"
+git_branch = "release"
+tag        = true
+"#,
+	);
+	let branch = &project.config.branches["master"];
+
+	let repo = Repository::init(&root).unwrap();
+	let mut index = repo.index().unwrap();
+	index
+		.add_all(["laspad.toml"].iter(), Default::default(), None)
+		.unwrap();
+	let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+	let signature = git2::Signature::now("test", "test@example.com").unwrap();
+	let commit = repo
+		.commit(Some("HEAD"), &signature, &signature, "init", &tree, &[])
+		.unwrap();
+
+	fs::write(root.join(".modid.master"), "1").unwrap();
+	let problems = check(&project, branch, "master").unwrap();
+	let fields: Vec<_> = problems.iter().map(|(_, p)| p.field.as_str()).collect();
+	assert_eq!(fields, vec!["branch.master.guard.git_branch"]);
+
+	fs::write(root.join("src/new.lua"), "").unwrap();
+	let problems = check(&project, branch, "master").unwrap();
+	assert_eq!(problems[0].0, Action::Fail);
+	assert_eq!(problems[0].1.field, "branch.master.guard.dirty");
+
+	assert_eq!(
+		tag(&project, branch, "master", commit).unwrap().unwrap(),
+		"workshop/master/1"
+	);
+	assert_eq!(
+		tag(&project, branch, "master", commit).unwrap().unwrap(),
+		"workshop/master/2"
+	);
+}
@@ -0,0 +1,169 @@
+use derive_more::{Display, From};
+use erroneous::Error as EError;
+use sha2::{Digest, Sha256};
+use std::{
+	collections::BTreeMap,
+	fmt,
+	io::{self, Read, Seek},
+};
+use zip::{result::ZipError, ZipArchive};
+
+#[derive(Debug, Display, EError, From)]
+pub enum Error {
+	#[display(fmt = "Could not parse Zip archive")]
+	ZipRead(#[error(source)] ZipError),
+	#[display(fmt = "Could not read file {} in Zip archive", _0)]
+	ZipReadFile(String, #[error(source)] io::Error),
+}
+
+pub struct Entry {
+	pub size:            u64,
+	pub compressed_size: u64,
+	pub crc32:           u32,
+}
+
+/// The contents of a packaged mod, as produced by `package::package`.
+pub struct Package {
+	pub entries: BTreeMap<String, Entry>,
+	pub modinfo: Option<String>,
+}
+
+impl Package {
+	pub fn read(r: impl Read + Seek) -> Result<Self, Error> {
+		let mut archive = ZipArchive::new(r)?;
+		let mut entries = BTreeMap::new();
+		let mut modinfo = None;
+		for i in 0..archive.len() {
+			let mut file = archive.by_index(i)?;
+			let name = file.name().to_owned();
+			if name.ends_with('/') {
+				continue;
+			}
+			if name == ".modinfo" {
+				let mut s = String::new();
+				file.read_to_string(&mut s)
+					.map_err(|e| Error::ZipReadFile(name.clone(), e))?;
+				modinfo = Some(s);
+			}
+			entries.insert(
+				name,
+				Entry {
+					size:            file.size(),
+					compressed_size: file.compressed_size(),
+					crc32:           file.crc32(),
+				},
+			);
+		}
+		Ok(Package { entries, modinfo })
+	}
+
+	pub fn size(&self) -> u64 {
+		self.entries.values().map(|e| e.size).sum()
+	}
+}
+
+/// A SHA-256 hash of the names and contents of the files in a package.
+/// Unlike a hash of the archive itself, it doesn't change with the timestamps in it.
+pub fn hash(r: impl Read + Seek) -> Result<String, Error> {
+	let mut archive = ZipArchive::new(r)?;
+	let mut files = BTreeMap::new();
+	for i in 0..archive.len() {
+		let mut file = archive.by_index(i)?;
+		let name = file.name().to_owned();
+		let mut data = Vec::new();
+		file.read_to_end(&mut data)
+			.map_err(|e| Error::ZipReadFile(name.clone(), e))?;
+		files.insert(name, data);
+	}
+
+	let mut hasher = Sha256::new();
+	for (name, data) in &files {
+		hasher.input(name.as_bytes());
+		hasher.input([0]);
+		hasher.input((data.len() as u64).to_le_bytes());
+		hasher.input(data);
+	}
+	Ok(format!("{:x}", hasher.result()))
+}
+
+impl fmt::Display for Package {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		for (name, entry) in &self.entries {
+			writeln!(
+				f,
+				"{:>10} {:>10} {}",
+				entry.size, entry.compressed_size, name
+			)?;
+		}
+		writeln!(
+			f,
+			"{} files, {} bytes uncompressed",
+			self.entries.len(),
+			self.size()
+		)?;
+		match &self.modinfo {
+			Some(modinfo) => write!(f, "\n.modinfo:\n{}\n", modinfo.trim_end()),
+			None => writeln!(f, "\nNo .modinfo present"),
+		}
+	}
+}
+
+/// The files that differ between two packages.
+pub struct Diff<'a> {
+	pub added:    Vec<&'a str>,
+	pub removed:  Vec<&'a str>,
+	pub modified: Vec<&'a str>,
+}
+
+impl<'a> Diff<'a> {
+	pub fn new(old: &'a Package, new: &'a Package) -> Self {
+		let mut diff = Diff {
+			added:    Vec::new(),
+			removed:  Vec::new(),
+			modified: Vec::new(),
+		};
+		for (name, entry) in &new.entries {
+			match old.entries.get(name) {
+				None => diff.added.push(name),
+				Some(old) if old.crc32 != entry.crc32 || old.size != entry.size => {
+					diff.modified.push(name)
+				},
+				Some(_) => (),
+			}
+		}
+		for name in old.entries.keys() {
+			if !new.entries.contains_key(name) {
+				diff.removed.push(name);
+			}
+		}
+		diff
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+	}
+}
+
+impl<'a> fmt::Display for Diff<'a> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		if self.is_empty() {
+			return writeln!(f, "The packages are identical");
+		}
+		for name in &self.added {
+			writeln!(f, "A {}", name)?;
+		}
+		for name in &self.removed {
+			writeln!(f, "D {}", name)?;
+		}
+		for name in &self.modified {
+			writeln!(f, "M {}", name)?;
+		}
+		writeln!(
+			f,
+			"{} added, {} removed, {} modified",
+			self.added.len(),
+			self.removed.len(),
+			self.modified.len()
+		)
+	}
+}
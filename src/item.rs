@@ -11,6 +11,10 @@ use std::{ops::Deref, str::FromStr};
 pub struct Item(steam::Item);
 
 impl Item {
+	/// Stands in for the ID of items that have not been created yet,
+	/// it is the longest possible so that lengths can be checked.
+	pub const PLACEHOLDER: Item = Item(steam::Item(u64::MAX));
+
 	pub fn url(&self) -> String {
 		format!(
 			"https://steamcommunity.com/sharedfiles/filedetails/?id={}",
@@ -0,0 +1,191 @@
+use chrono::Utc;
+use derive_more::Display;
+use erroneous::Error as EError;
+use serde_derive::{Deserialize, Serialize};
+use std::{
+	fmt,
+	fs::{self, OpenOptions},
+	io::{self, Write},
+	path::PathBuf,
+};
+
+use crate::{item::Item, project::Project};
+
+#[derive(Debug, Display, EError)]
+pub enum Error {
+	#[display(fmt = "Could not read '{}'", "_0.display()")]
+	Read(PathBuf, #[error(source)] io::Error),
+	#[display(fmt = "Line {} of '{}' is not a valid entry", _1, "_0.display()")]
+	Parse(PathBuf, usize, #[error(source)] serde_json::Error),
+	#[display(fmt = "Could not write to '{}'", "_0.display()")]
+	Write(PathBuf, #[error(source)] io::Error),
+}
+
+/// The steps of publishing a branch, in the order they happen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+	Started,
+	ItemCreated,
+	ZipWritten,
+	PreviewWritten,
+	GalleryWritten,
+	ItemUpdated,
+}
+
+impl fmt::Display for Step {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			Step::Started => "start",
+			Step::ItemCreated => "create the workshop item",
+			Step::ZipWritten => "write the zip",
+			Step::PreviewWritten => "write the preview",
+			Step::GalleryWritten => "update the screenshots and videos",
+			Step::ItemUpdated => "update the workshop item",
+		})
+	}
+}
+
+/// A single line of the journal.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Entry {
+	/// When the step finished, in UTC
+	pub time:   String,
+	pub branch: String,
+	/// The ID of the workshop item, as in `.modid.<branch>`
+	pub item:   Option<String>,
+	/// The git commit that was published
+	pub commit: Option<String>,
+	/// The hash of the package, see `inspect::hash`
+	pub hash:   String,
+	pub step:   Step,
+	/// Why the step failed, if it did
+	pub error:  Option<String>,
+}
+
+impl Entry {
+	pub fn item(&self) -> Option<Item> {
+		self.item.as_ref().and_then(|i| i.parse().ok())
+	}
+}
+
+/// The history of publishing the branches of a project, kept in `.publish_journal`
+/// with one JSON object per line.
+pub struct Journal {
+	path:        PathBuf,
+	pub entries: Vec<Entry>,
+}
+
+impl Journal {
+	pub const PATH: &'static str = ".publish_journal";
+
+	pub fn open(project: &Project) -> Result<Self, Error> {
+		let path = project.path.join(Journal::PATH);
+		let mut entries = Vec::new();
+		if path.exists() {
+			let content = fs::read_to_string(&path).map_err(|e| Error::Read(path.clone(), e))?;
+			for (i, line) in content.lines().enumerate() {
+				if line.trim().is_empty() {
+					continue;
+				}
+				entries.push(
+					serde_json::from_str(line).map_err(|e| Error::Parse(path.clone(), i + 1, e))?,
+				);
+			}
+		}
+		Ok(Journal { path, entries })
+	}
+
+	pub fn record(&mut self, entry: Entry) -> Result<(), Error> {
+		let mut line = serde_json::to_string(&entry).expect("Could not serialize journal entry");
+		line.push('\n');
+		OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(&self.path)
+			.and_then(|mut f| f.write_all(line.as_bytes()))
+			.map_err(|e| Error::Write(self.path.clone(), e))?;
+		self.entries.push(entry);
+		Ok(())
+	}
+
+	/// Every attempt at publishing, oldest first, optionally only those of one branch.
+	/// An attempt starts with a `Step::Started` entry.
+	pub fn runs(&self, branch: Option<&str>) -> Vec<Vec<&Entry>> {
+		let mut runs: Vec<Vec<&Entry>> = Vec::new();
+		for entry in &self.entries {
+			match branch {
+				Some(branch) if branch != entry.branch => continue,
+				_ => (),
+			}
+			if entry.step == Step::Started {
+				runs.push(vec![entry]);
+			} else if let Some(run) = runs.iter_mut().rev().find(|r| r[0].branch == entry.branch) {
+				run.push(entry);
+			}
+		}
+		runs
+	}
+}
+
+/// Records the steps of one attempt at publishing a branch.
+pub struct Run<'a> {
+	pub journal: &'a mut Journal,
+	pub branch:  String,
+	pub commit:  Option<String>,
+	pub hash:    String,
+	pub item:    Option<Item>,
+}
+
+impl<'a> Run<'a> {
+	pub fn start(&mut self) -> Result<(), Error> {
+		self.entry(Step::Started, None)
+	}
+
+	pub fn record<T, E: fmt::Display>(
+		&mut self,
+		step: Step,
+		result: &Result<T, E>,
+	) -> Result<(), Error> {
+		self.entry(step, result.as_ref().err().map(|e| e.to_string()))
+	}
+
+	fn entry(&mut self, step: Step, error: Option<String>) -> Result<(), Error> {
+		let entry = Entry {
+			time: Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+			branch: self.branch.clone(),
+			item: self.item.map(|i| format!("{:X}", i)),
+			commit: self.commit.clone(),
+			hash: self.hash.clone(),
+			step,
+			error,
+		};
+		self.journal.record(entry)
+	}
+}
+
+/// A one line summary of an attempt at publishing, as returned by `Journal::runs`.
+pub struct Summary<'a>(pub &'a [&'a Entry]);
+
+impl<'a> fmt::Display for Summary<'a> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let first = self.0[0];
+		let last = self.0[self.0.len() - 1];
+		let item = self.0.iter().rev().find_map(|e| e.item.as_ref());
+		let commit = first.commit.as_ref().map(|c| &c[..c.len().min(8)]);
+		write!(
+			f,
+			"{}  {}  {}  {}  ",
+			first.time,
+			first.branch,
+			item.map_or("-", String::as_str),
+			commit.unwrap_or("-")
+		)?;
+		match (&last.error, last.step) {
+			(Some(error), step) => write!(f, "failed to {}: {}", step, error),
+			(None, Step::ItemUpdated) => write!(f, "published"),
+			(None, Step::Started) => write!(f, "interrupted before any step"),
+			(None, step) => write!(f, "interrupted, the last step was to {}", step),
+		}
+	}
+}
@@ -0,0 +1,191 @@
+use derive_more::{Display, From};
+use erroneous::Error as EError;
+use static_lua::{Document, Value};
+use std::{fs, io, path::PathBuf};
+
+use crate::{
+	config::{Branch, DescriptionError},
+	item::Item,
+	project::{ModIDError, Project},
+};
+
+#[derive(Debug, Display, EError, From)]
+pub enum Error {
+	#[display(fmt = "Could not read the existing mod.settings")]
+	Read(#[error(source)] io::Error),
+	#[display(fmt = "Could not parse the existing mod.settings")]
+	Parse(#[error(source)] static_lua::Error),
+	#[display(fmt = "Could not write '{}'", "_0.display()")]
+	Write(PathBuf, #[error(source)] io::Error),
+	#[display(fmt = "Could not get the Mod ID")]
+	ModID(#[error(source)] ModIDError),
+	#[display(fmt = "Could not generate the description")]
+	Description(#[error(source)] DescriptionError),
+}
+
+fn string(s: &str) -> Value<'_> {
+	Value::String(s.into())
+}
+
+/// The key of a new `tag_*` assignment for `tag`, e.g. `tag_gameplay_tweak`.
+fn tag_key(tag: &str, taken: &[String]) -> String {
+	let slug: String = tag
+		.chars()
+		.map(|c| {
+			if c.is_ascii_alphanumeric() {
+				c.to_ascii_lowercase()
+			} else {
+				'_'
+			}
+		})
+		.collect();
+	let key = format!("tag_{}", slug.trim_matches('_'));
+	let mut unique = key.clone();
+	let mut i = 2;
+	while taken.contains(&unique) {
+		unique = format!("{}_{}", key, i);
+		i += 1;
+	}
+	unique
+}
+
+/// Updates `document` to describe the branch,
+/// keeping its comments and the assignments Launch Pad doesn't know.
+pub fn update(
+	project: &Project,
+	branch: &Branch,
+	branch_name: &str,
+	document: &mut Document,
+) -> Result<(), Error> {
+	let item = project.modid(branch, branch_name)?;
+	let old = document.to_string();
+	let old = static_lua::parse(&old)?;
+
+	document.set("name", &string(&branch.name));
+	let description = branch.description(project, item.unwrap_or(Item::PLACEHOLDER))?;
+	document.set("description", &string(&description));
+	let image = branch
+		.preview
+		.as_ref()
+		.map_or(String::new(), |p| p.to_string_lossy().into_owned());
+	document.set("image", &string(&image));
+
+	// Existing tags keep their keys, since Launch Pad names them after their category
+	let mut keys: Vec<String> = document
+		.names()
+		.filter(|n| n.starts_with("tag_"))
+		.map(String::from)
+		.collect();
+	keys.dedup();
+	let mut present = Vec::new();
+	for key in &keys {
+		match old.get(key.as_str()).and_then(|v| v.as_str()) {
+			Some(tag) if branch.tags.iter().any(|t| t == tag) && !present.contains(&tag) => {
+				present.push(tag);
+			},
+			_ => {
+				document.remove(key);
+			},
+		}
+	}
+	for tag in &branch.tags {
+		if !present.contains(&tag.as_str()) {
+			let key = tag_key(tag, &keys);
+			document.set(&key, &string(tag));
+			keys.push(key);
+		}
+	}
+
+	// Launch Pad publishes a new item without one
+	if let Some(item) = item {
+		document.set("publish_id", &string(&format!("{:x}", item)));
+	}
+	let (source_dir, output_dir) = match &project.config.source_output_dir {
+		Some((source_dir, output_dir)) => (
+			source_dir.to_string_lossy().into_owned(),
+			output_dir.to_string_lossy().into_owned(),
+		),
+		// What `laspad compile` reads and writes
+		None => ("src".into(), "compiled".into()),
+	};
+	document.set("source_dir", &string(&source_dir));
+	document.set("output_dir", &string(&output_dir));
+	Ok(())
+}
+
+/// Writes the branch into the `mod.settings` of the project, for Launch Pad.
+/// An existing one is updated in place.
+pub fn export(project: &Project, branch: &Branch, branch_name: &str) -> Result<PathBuf, Error> {
+	let path = project.path.join("mod.settings");
+	let mut document = match fs::read_to_string(&path) {
+		Ok(s) => Document::parse(&s)?,
+		Err(ref e) if e.kind() == io::ErrorKind::NotFound => Document::default(),
+		Err(e) => return Err(e.into()),
+	};
+	update(project, branch, branch_name, &mut document)?;
+	fs::write(&path, document.to_string()).map_err(|e| Error::Write(path.clone(), e))?;
+	Ok(path)
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+	let root = crate::util::TempDir::new("launchpad");
+	fs::write(root.join("description.txt"), "A\nmod").unwrap();
+	let project = Project::write(
+		&root,
+		r#"version = 2
+[branch.master]
+name            = "Test"
+tags            = ["Gameplay Tweak", "New Tag"]
+description     = "description.txt"
+autodescription = false
+preview         = "preview.jpg"
+item            = "1A"
+[branch.beta]
+extends         = "master"
+"#,
+	);
+	let branch = project.config.branch("master").unwrap();
+
+	let mut document = Document::parse(
+		r#"-- Edited by hand
+name        = "Old"
+tag_modtype = "Gameplay Tweak"
+tag_support = "Must be run on Server"
+custom      = 1
+"#,
+	)
+	.unwrap();
+	update(&project, branch, "master", &mut document).unwrap();
+	assert_eq!(
+		document.to_string(),
+		r#"-- Edited by hand
+name        = "Test"
+tag_modtype = "Gameplay Tweak"
+custom      = 1
+description = [[A
+mod]]
+image       = "preview.jpg"
+tag_new_tag = "New Tag"
+publish_id  = "1a"
+source_dir  = "src"
+output_dir  = "compiled"
+"#
+	);
+
+	let path = export(&project, branch, "master").unwrap();
+	let settings = fs::read_to_string(path).unwrap();
+	let settings = static_lua::parse(&settings).unwrap();
+	assert_eq!(settings["name"].as_str(), Some("Test"));
+	assert_eq!(
+		settings["tag_gameplay_tweak"].as_str(),
+		Some("Gameplay Tweak")
+	);
+	assert_eq!(settings["output_dir"].as_str(), Some("compiled"));
+
+	let mut document = Document::default();
+	let beta = project.config.branch("beta").unwrap();
+	update(&project, beta, "beta", &mut document).unwrap();
+	assert!(!document.names().any(|n| n == "publish_id"));
+}
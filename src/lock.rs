@@ -0,0 +1,201 @@
+use derive_more::Display;
+use erroneous::Error as EError;
+use git2::{build::CheckoutBuilder, ObjectType, Oid, Repository};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+	fs,
+	io,
+	path::{Path, PathBuf},
+};
+
+use crate::project::Project;
+
+#[derive(Debug, Display, EError)]
+pub enum Error {
+	#[display(fmt = "Could not read '{}'", "_0.display()")]
+	Read(PathBuf, #[error(source)] io::Error),
+	#[display(fmt = "'{}' is not a valid lockfile", "_0.display()")]
+	Parse(PathBuf, #[error(source)] toml::de::Error),
+	#[display(fmt = "Could not write '{}'", "_0.display()")]
+	Write(PathBuf, #[error(source)] io::Error),
+	#[display(fmt = "Could not clone or fetch '{}'", _0)]
+	Fetch(String, #[error(source)] git2::Error),
+	#[display(fmt = "Could not find the revision '{}' of '{}'", _1, _0)]
+	Revision(String, String, #[error(source)] git2::Error),
+	#[display(fmt = "Could not check out {} of '{}'", _1, _0)]
+	Checkout(String, Oid, #[error(source)] git2::Error),
+}
+
+/// The revision a git dependency was last updated to.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Locked {
+	pub git: String,
+	pub rev: String,
+}
+
+/// `laspad.lock`, which records the revisions of git dependencies,
+/// so that the published content can be traced back to them.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Lock {
+	#[serde(default)]
+	pub dependencies: Vec<Locked>,
+}
+
+impl Lock {
+	pub const PATH: &'static str = "laspad.lock";
+
+	/// Reads the lockfile of the project, which is empty if it doesn't exist yet.
+	pub fn read(project: &Project) -> Result<Self, Error> {
+		let path = project.path.join(Lock::PATH);
+		match fs::read_to_string(&path) {
+			Ok(s) => toml::from_str(&s).map_err(|e| Error::Parse(path, e)),
+			Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Lock::default()),
+			Err(e) => Err(Error::Read(path, e)),
+		}
+	}
+
+	pub fn write(&self, project: &Project) -> Result<(), Error> {
+		let path = project.path.join(Lock::PATH);
+		let content = toml::to_string(self).expect("Could not serialize lockfile");
+		let content = format!("# Written by `laspad update`, do not edit\n{}", content);
+		fs::write(&path, content).map_err(|e| Error::Write(path, e))
+	}
+
+	pub fn get(&self, git: &str) -> Option<&str> {
+		self.dependencies
+			.iter()
+			.find(|l| l.git == git)
+			.map(|l| l.rev.as_str())
+	}
+
+	pub fn set(&mut self, git: &str, rev: Oid) {
+		let rev = rev.to_string();
+		match self.dependencies.iter_mut().find(|l| l.git == git) {
+			Some(locked) => locked.rev = rev,
+			None => self.dependencies.push(Locked {
+				git: git.into(),
+				rev,
+			}),
+		}
+	}
+}
+
+/// Clones `url` into `dir`, or fetches it if it has been cloned already,
+/// and checks out `rev`, or the default branch of the remote if `None`.
+pub fn fetch(url: &str, rev: Option<&str>, dir: &Path) -> Result<Oid, Error> {
+	let fetch_error = |e| Error::Fetch(url.into(), e);
+	let repo = if dir.join(".git").exists() {
+		let repo = Repository::open(dir).map_err(fetch_error)?;
+		repo.find_remote("origin")
+			.and_then(|mut origin| {
+				origin.fetch(
+					&[
+						"+refs/heads/*:refs/remotes/origin/*",
+						"+refs/tags/*:refs/tags/*",
+					],
+					None,
+					None,
+				)
+			})
+			.map_err(fetch_error)?;
+		repo
+	} else {
+		info!("Cloning {}", url);
+		if let Some(parent) = dir.parent() {
+			fs::create_dir_all(parent).map_err(|e| Error::Write(parent.into(), e))?;
+		}
+		Repository::clone(url, dir).map_err(fetch_error)?
+	};
+
+	// Branches only exist as remote branches in the clone
+	let rev = rev.unwrap_or("HEAD");
+	let object = repo
+		.revparse_single(&format!("origin/{}", rev))
+		.or_else(|_| repo.revparse_single(rev))
+		.and_then(|o| o.peel(ObjectType::Commit))
+		.map_err(|e| Error::Revision(url.into(), rev.into(), e))?;
+	let oid = object.id();
+	repo.checkout_tree(&object, Some(CheckoutBuilder::new().force()))
+		.and_then(|()| repo.set_head_detached(oid))
+		.map_err(|e| Error::Checkout(url.into(), oid, e))?;
+	Ok(oid)
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+	let root = crate::util::TempDir::new("lock");
+	fs::create_dir_all(root.join("upstream")).unwrap();
+	let project = Project::write(
+		root.join("project"),
+		"version = 1\n[branch.master]\nname = \"Test\"\ntags = []\n",
+	);
+
+	let upstream = Repository::init(root.join("upstream")).unwrap();
+	let signature = git2::Signature::now("test", "test@example.com").unwrap();
+	let commit = |message: &str, content: &str| {
+		fs::write(root.join("upstream/init.lua"), content).unwrap();
+		let mut index = upstream.index().unwrap();
+		index
+			.add_all(["init.lua"].iter(), Default::default(), None)
+			.unwrap();
+		let tree = upstream.find_tree(index.write_tree().unwrap()).unwrap();
+		let parent = upstream.head().ok().and_then(|h| h.peel_to_commit().ok());
+		let parents: Vec<_> = parent.iter().collect();
+		upstream
+			.commit(Some("HEAD"), &signature, &signature, message, &tree, &parents)
+			.unwrap()
+	};
+	let first = commit("first", "first");
+	upstream
+		.tag_lightweight(
+			"v1",
+			&upstream.find_object(first, None).unwrap(),
+			false,
+		)
+		.unwrap();
+	let second = commit("second", "second");
+
+	let url = root.join("upstream").to_string_lossy().into_owned();
+	let dir = root.join("clone");
+	assert_eq!(fetch(&url, None, &dir).unwrap(), second);
+	assert_eq!(fetch(&url, Some("v1"), &dir).unwrap(), first);
+	assert_eq!(fs::read_to_string(dir.join("init.lua")).unwrap(), "first");
+	let third = commit("third", "third");
+	assert_eq!(fetch(&url, None, &dir).unwrap(), third);
+
+	let mut lock = Lock::read(&project).unwrap();
+	assert!(lock.dependencies.is_empty());
+	lock.set(&url, first);
+	lock.set(&url, third);
+	lock.write(&project).unwrap();
+	let lock = Lock::read(&project).unwrap();
+	assert_eq!(lock.get(&url), Some(third.to_string().as_str()));
+	assert_eq!(lock.dependencies.len(), 1);
+
+	// Without a `rev`, the dependency stays at the locked revision until it is updated explicitly
+	let project = Project::write(
+		root.join("project"),
+		format!(
+			"version = 1\ndependencies = [{{ git = '{}' }}]\n\
+			 [branch.master]\nname = \"Test\"\ntags = []\n",
+			url
+		),
+	);
+	let mut lock = Lock::read(&project).unwrap();
+	lock.set(&url, first);
+	lock.write(&project).unwrap();
+	let head = || {
+		Repository::open(project.path_for_git(&url))
+			.unwrap()
+			.head()
+			.unwrap()
+			.target()
+	};
+	project.update(project.config.all_deps(), false).unwrap();
+	assert_eq!(head(), Some(first));
+	project.update(project.config.all_deps(), true).unwrap();
+	assert_eq!(head(), Some(third));
+	let lock = Lock::read(&project).unwrap();
+	assert_eq!(lock.get(&url), Some(third.to_string().as_str()));
+}
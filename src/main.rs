@@ -1,28 +1,48 @@
 #[macro_use]
 extern crate log;
 
+mod changelog;
+mod check;
 mod compile;
 mod config;
+mod deps;
 mod download;
+mod gc;
+mod guard;
+mod inspect;
 mod item;
+mod journal;
+mod launchpad;
+mod lock;
+mod migrate;
+mod modid;
 mod package;
+mod preview;
 mod project;
 mod publish;
+mod publisher;
+mod retry;
+mod scan;
+mod steamcmd;
 mod util;
+mod validate;
 
-use clap::{clap_app, crate_version};
+use clap::{clap_app, crate_version, Arg, SubCommand};
 use derive_more::{Display, From};
 use erroneous::Error as EError;
 use std::{
 	fmt,
 	fs::{self, File},
-	io,
+	io::{self, Cursor},
 	path::{Path, PathBuf},
+	rc::Rc,
+	time::Duration,
 };
 
 use self::{
 	item::{Item, ItemParseError},
 	project::Project,
+	publisher::Publisher,
 };
 
 #[derive(Display, EError, From)]
@@ -37,7 +57,7 @@ enum Error {
 	DownloadError(#[error(source)] download::Error),
 	#[display(fmt = "Could not compile project")]
 	CompileError(#[error(source)] compile::Error),
-	#[display(fmt = "Could not update steam dependencies")]
+	#[display(fmt = "Could not update dependencies")]
 	ProjectUpdateError(#[error(source)] project::UpdateError),
 	#[display(fmt = "Could not parse '{}'", _0)]
 	ItemParseError(String, #[error(source)] ItemParseError),
@@ -47,8 +67,48 @@ enum Error {
 	PackageError(#[error(source)] package::Error),
 	#[display(fmt = "Could not publish project")]
 	PublishError(#[error(source)] publish::Error),
+	#[display(fmt = "Could not connect to the workshop")]
+	PublisherError(#[error(source)] publisher::Error),
 	#[display(fmt = "Could not remove existing 'compiled' directory")]
 	RemoveCompiled(#[error(source)] io::Error),
+	#[display(fmt = "Could not open '{}'", _0)]
+	OpenFile(String, #[error(source)] io::Error),
+	#[display(fmt = "Could not inspect package")]
+	InspectError(#[error(source)] inspect::Error),
+	#[display(fmt = "Could not get Mod ID")]
+	ModIDError(#[error(source)] project::ModIDError),
+	#[display(fmt = "The branch '{}' has not been published yet", _0)]
+	NotPublished(String),
+	#[display(fmt = "Could not generate change notes")]
+	ChangelogError(#[error(source)] changelog::Error),
+	#[display(fmt = "{} problems found", _0)]
+	CheckFailed(usize),
+	#[display(fmt = "'{}' is not a valid number of seconds", _0)]
+	InvalidTimeout(String),
+	#[display(fmt = "Could not read the publish journal")]
+	JournalError(#[error(source)] journal::Error),
+	#[display(fmt = "{} branches could not be published", _0)]
+	PublishFailed(usize),
+	#[display(
+		fmt = "The branches '{}' and '{}' are for different apps, which the Steam client can't publish at once; publish them separately or with --steamcmd",
+		_0,
+		_1
+	)]
+	MixedAppIds(String, String),
+	#[display(fmt = "Could not manage the Mod ID")]
+	ModIDManageError(#[error(source)] modid::Error),
+	#[display(fmt = "{}", _0)]
+	UnknownBranch(#[error(defer)] config::UnknownBranch),
+	#[display(fmt = "Could not change the dependencies")]
+	DepsError(#[error(source)] deps::Error),
+	#[display(fmt = "Could not remove unused dependencies")]
+	GCError(#[error(source)] gc::Error),
+	#[display(fmt = "Could not export mod.settings")]
+	LaunchpadError(#[error(source)] launchpad::Error),
+	#[display(fmt = "Could not migrate the configuration")]
+	MigrateError(#[error(source)] migrate::Error),
+	#[display(fmt = "{}", _0)]
+	ValidateError(#[error(defer)] util::ReadError),
 }
 
 impl fmt::Debug for Error {
@@ -65,6 +125,11 @@ impl fmt::Debug for Error {
 	}
 }
 
+fn open_package(path: &str) -> Result<inspect::Package, Error> {
+	let file = File::open(path).map_err(|e| Error::OpenFile(path.into(), e))?;
+	Ok(inspect::Package::read(file)?)
+}
+
 fn main() -> Result<(), Error> {
 	let matches = clap_app!(laspad =>
 		(version: crate_version!())
@@ -77,8 +142,20 @@ fn main() -> Result<(), Error> {
 		 	(about: "Initialises laspad in the current directory")
 		)
 		(@subcommand update =>
-			(about: "Updates dependencies")
-			(@arg ITEMS: #{0, u64::max_value()} "Steam items to update, none will mean all")
+			(about: "Downloads workshop dependencies and fetches git ones, recording their revisions in laspad.lock")
+			(@arg ITEMS: #{0, u64::max_value()} "Steam items or URLs of git dependencies to update to their newest revision, none will mean all dependencies with git ones at the revision in laspad.lock")
+		)
+		(@subcommand gc =>
+			(about: "Removes downloaded and cloned dependencies the project doesn't use anymore")
+			(@arg INSTALLS: --installs +takes_value "Instead removes all but the newest version of each item installed into this directory")
+		)
+		(@subcommand add =>
+			(about: "Adds workshop items to the dependencies in laspad.toml and downloads them")
+			(@arg ITEMS: +multiple +required "The workshop items, as Mod IDs or URLs")
+		)
+		(@subcommand remove =>
+			(about: "Removes workshop items from the dependencies in laspad.toml and deletes their downloads")
+			(@arg ITEMS: +multiple +required "The workshop items, as Mod IDs or URLs")
 		)
 		(@subcommand download =>
 			(about: "Download and extract mod from workshop into target folder")
@@ -95,6 +172,7 @@ Merges the dependencies and the `src` folder together into the `compiled` folder
 NB: The files in the `compiled` folder are actually hard links.
 This means that changes in the compiled files will be reflected in the source and
 vice versa.")
+			(@arg BRANCH: --branch +takes_value "Compiles the dependencies and overlays of this branch too")
 		)
 		(@subcommand package =>
 			(about: "Compiles the mod and then packages into a zip file which can be published")
@@ -103,7 +181,67 @@ vice versa.")
 		)
 		(@subcommand publish =>
 			(about: "Updates dependencies and then publishes the mod to workshop")
-			(@arg BRANCH: "The branch to publish, defaults to master")
+			(@arg BRANCHES: ... "The branches to publish, defaults to master")
+			(@arg ALL: --all conflicts_with[BRANCHES] "Publishes every branch")
+			(@arg DRYRUN: --("dry-run") "Writes what would be published into a directory instead of uploading it")
+			(@arg OUT: --out +takes_value requires[DRYRUN] "Where to write the dry run, defaults to `dry-run/<branch>`. With several branches, each is written to `<out>/<branch>`")
+			(@arg FAKE: --("fake-workshop") +takes_value conflicts_with[DRYRUN] "Publishes to a fake workshop in this directory instead of Steam, for testing")
+			(@arg STEAMCMD: --steamcmd +takes_value conflicts_with[DRYRUN FAKE] "Publishes through steamcmd logged in as this Steam user, instead of through the Steam client")
+			(@arg STEAMCMDPATH: --("steamcmd-path") +takes_value requires[STEAMCMD] "The steamcmd binary to use, defaults to `steamcmd`")
+			(@arg TIMEOUT: --timeout +takes_value "How many seconds in total to wait for a busy Steam per branch before giving up, defaults to 600")
+			(@arg RESUME: --resume conflicts_with[DRYRUN] "Continues the last publish of the branch where it failed")
+		)
+		(@subcommand modid =>
+			(about: "Shows and changes the Mod IDs of branches, kept either in laspad.toml or in `.modid.<branch>`")
+			(@subcommand show =>
+				(about: "Shows the Mod IDs of branches, this is the default")
+				(@arg BRANCHES: ... "The branches, none will mean all")
+			)
+			(@subcommand set =>
+				(about: "Sets the Mod ID of a branch, e.g. to adopt an existing workshop item")
+				(@arg BRANCH: +required "The branch")
+				(@arg ITEM: +required "The workshop item, as a hexadecimal ID or a URL")
+				(@arg CONFIG: --config "Writes it into laspad.toml as `item`")
+			)
+			(@subcommand clear =>
+				(about: "Forgets the Mod ID of a branch, so that the next publish creates a new workshop item")
+				(@arg BRANCH: +required "The branch")
+			)
+			(@subcommand migrate =>
+				(about: "Moves Mod IDs from `.modid.<branch>` into laspad.toml")
+				(@arg BRANCHES: ... "The branches, none will mean all")
+			)
+		)
+		(@subcommand migrate =>
+			(about: "Converts a Launch Pad mod.settings or an older laspad.toml into the latest laspad.toml, printing the changes before writing them")
+			(@arg DRYRUN: --("dry-run") "Only prints the changes")
+		)
+		(@subcommand branches =>
+			(about: "Lists the branches with their names, Mod IDs, tags and previews")
+		)
+		(@subcommand history =>
+			(about: "Shows past publishes from the publish journal")
+			(@arg BRANCH: "Only show the publishes of this branch")
+		)
+		(@subcommand check =>
+			(about: "Checks laspad.toml strictly and that branches can be published without problems on the workshop")
+			(@arg BRANCHES: ... "The branches to check, none will mean all")
+		)
+		(@subcommand changes =>
+			(about: "Shows the change notes that would be published for the branch")
+			(@arg BRANCH: "The branch, defaults to master")
+		)
+		(@subcommand inspect =>
+			(about: "Lists the files in a packaged mod, their sizes and its .modinfo")
+			(@arg PATH: +required "The zip file to inspect")
+		)
+		(@subcommand diff =>
+			(about: "\
+Compares two packages and reports added, removed and modified files.
+With only one package, it is compared against the published workshop item of the branch.
+With none, the current build of the branch is compared against the published workshop item.")
+			(@arg PACKAGES: #{0, 2} "The zip files to compare, the old one first")
+			(@arg BRANCH: -b --branch +takes_value "The branch to compare against, defaults to master")
 		)
 		/* FIXME
 		(@subcommand prepare =>
@@ -123,7 +261,14 @@ vice versa.")
 			)
 		)
 		*/
-	).get_matches();
+	)
+	// `clap_app!` only takes identifiers as subcommand names
+	.subcommand(
+		SubCommand::with_name("export-launchpad")
+			.about("Writes the branch into mod.settings for Launch Pad, keeping the comments and other settings of an existing one")
+			.arg(Arg::from_usage("[BRANCH] 'The branch, defaults to master'")),
+	)
+	.get_matches();
 
 	fern::Dispatch::new()
 		.format(|out, message, record| {
@@ -148,7 +293,21 @@ vice versa.")
 	// FIXME
 	let mut path: &Path = &Path::canonicalize(".".as_ref()).unwrap();
 	let project = loop {
-		if let Some(project) = Project::get(path)? {
+		let project = match Project::get(path) {
+			// Shows where the configuration is wrong, which the error can't
+			Err(e) if matches.subcommand_name() == Some("check") => {
+				let problems = validate::config(path)?;
+				if problems.is_empty() {
+					return Err(e.into());
+				}
+				for problem in &problems {
+					println!("{}", problem);
+				}
+				return Err(Error::CheckFailed(problems.len()));
+			},
+			project => project?,
+		};
+		if let Some(project) = project {
 			break Some(project);
 		}
 
@@ -178,24 +337,102 @@ vice versa.")
 				download::install(item, ".")?;
 			}
 		},
+		("gc", Some(m)) => {
+			let report = match m.value_of("INSTALLS") {
+				Some(path) => gc::installs(path.as_ref())?,
+				None => gc::dependencies(project.as_ref().ok_or(Error::NoProject)?)?,
+			};
+			println!("{}", report);
+		},
+		("inspect", Some(m)) => {
+			let path = m.value_of("PATH").expect("Could not get PATH");
+			print!("{}", open_package(path)?);
+		},
+		("diff", Some(m)) => {
+			let branch = m.value_of("BRANCH").unwrap_or("master");
+			let project = project.as_ref();
+			let published = || -> Result<inspect::Package, Error> {
+				let project = project.ok_or(Error::NoProject)?;
+				let item = project
+					.modid(project.config.branch(branch)?, branch)?
+					.ok_or_else(|| Error::NotPublished(branch.into()))?;
+				info!("Downloading published workshop item {}", item);
+				Ok(inspect::Package::read(Cursor::new(download::fetch(item)?))?)
+			};
+			let packages: Vec<_> = m.values_of("PACKAGES").map_or(Vec::new(), |p| p.collect());
+			let (old, new) = match packages[..] {
+				[old, new] => (open_package(old)?, open_package(new)?),
+				[new] => (published()?, open_package(new)?),
+				_ => {
+					let project = project.ok_or(Error::NoProject)?;
+					let zip = package::package(
+						project,
+						project.config.branch(branch)?,
+						Cursor::new(Vec::new()),
+					)?
+					.into_inner();
+					(published()?, inspect::Package::read(Cursor::new(zip))?)
+				},
+			};
+			print!("{}", inspect::Diff::new(&old, &new));
+		},
 		(cmd, m) => {
 			let project = project.ok_or(Error::NoProject)?;
 			match (cmd, m) {
 				("update", m) => {
+					let all = project.config.all_deps();
 					match m.and_then(|m| m.values_of("ITEMS")) {
 						Some(items) => {
-							let items: Result<Vec<_>, _> = items
+							let deps: Result<Vec<_>, _> = items
 								.map(|i| {
-									i.parse()
-										.map_err(|e| Error::ItemParseError(i.to_owned(), e))
+									let git = all.iter().find(|d| match d {
+										config::Dep::Git { git, .. } => git == i,
+										_ => false,
+									});
+									match git {
+										Some(dep) => Ok(dep.clone()),
+										None => i
+											.parse()
+											.map(config::Dep::Workshop)
+											.map_err(|e| Error::ItemParseError(i.to_owned(), e)),
+									}
 								})
 								.collect();
-							project.update(items?)?;
+							project.update(deps?, true)?;
 						},
-						None => project.update(project.config.deps.clone())?,
+						None => project.update(all, false)?,
 					};
+					let report = gc::dependencies(&project)?;
+					if !report.removed.is_empty() {
+						println!("{}", report);
+					}
 				},
-				("compile", _) => {
+				("add", Some(m)) => {
+					let items = m.values_of("ITEMS").expect("Could not get ITEMS");
+					let items: Vec<Item> = items
+						.map(|i| i.parse().map_err(|e| (i.to_owned(), e)))
+						.collect::<Result<_, _>>()?;
+					let added = deps::add(&project, &items)?;
+					let project = Project::get(&project.path)?.ok_or(Error::NoProject)?;
+					project.update(
+						added.iter().map(|&(i, _)| config::Dep::Workshop(i)).collect(),
+						false,
+					)?;
+					for (item, title) in added {
+						println!("Added {:X} ({})", item, title);
+					}
+				},
+				("remove", Some(m)) => {
+					let items = m.values_of("ITEMS").expect("Could not get ITEMS");
+					let items: Vec<Item> = items
+						.map(|i| i.parse().map_err(|e| (i.to_owned(), e)))
+						.collect::<Result<_, _>>()?;
+					deps::remove(&project, &items)?;
+					for item in items {
+						println!("Removed {:X}", item);
+					}
+				},
+				("compile", Some(m)) => {
 					struct Target {
 						path: PathBuf,
 					}
@@ -218,17 +455,246 @@ vice versa.")
 					if out.path.exists() {
 						fs::remove_dir_all(&out.path).map_err(Error::RemoveCompiled)?;
 					}
-					compile::compile(&project, &mut out)?;
+					let branch = match m.value_of("BRANCH") {
+						Some(branch) => Some(project.config.branch(branch)?),
+						None => None,
+					};
+					compile::compile(&project, branch, &mut out)?;
 				},
 				("package", Some(m)) => {
 					let branch = m.value_of("BRANCH").unwrap_or("master");
+					let branch = project.config.branch(branch)?;
 					let path = m.value_of("PATH").expect("Could not get PATH");
 					let file = File::create(path).map_err(Error::CreateFile)?;
-					package::package(&project, &project.config.branches[branch], file)?;
+					package::package(&project, branch, file)?;
 				},
 				("publish", Some(m)) => {
+					// Publishing would otherwise ignore most of them, like a misspelled key
+					for problem in validate::config(&project.path)? {
+						warn!("{}", problem);
+					}
+					let names: Vec<&str> = if m.is_present("ALL") {
+						project.config.branch_names()
+					} else {
+						m.values_of("BRANCHES")
+							.map_or(vec!["master"], |branches| branches.collect())
+					};
+					let branches = names
+						.iter()
+						.map(|&name| Ok((name, project.config.branch(name)?)))
+						.collect::<Result<Vec<_>, Error>>()?;
+					let compiled = package::Compiled::for_branches(
+						&project,
+						&branches.iter().map(|&(_, b)| b).collect::<Vec<_>>(),
+					)?;
+					let branches: Vec<_> = branches
+						.into_iter()
+						.zip(compiled)
+						.map(|((name, branch), compiled)| (name, branch, compiled))
+						.collect();
+					if m.is_present("DRYRUN") {
+						for &(name, branch, ref compiled) in &branches {
+							let out = match m.value_of("OUT") {
+								Some(out) if branches.len() > 1 => Path::new(out).join(name),
+								Some(out) => out.into(),
+								None => project.path.join("dry-run").join(name),
+							};
+							publish::dry_run(&project, compiled, branch, name, &out)?;
+						}
+					} else {
+						let mut retry = retry::Retry::default();
+						if let Some(timeout) = m.value_of("TIMEOUT") {
+							let secs = timeout
+								.parse()
+								.map_err(|_| Error::InvalidTimeout(timeout.into()))?;
+							retry.deadline = Duration::from_secs(secs);
+						}
+						let resume = m.is_present("RESUME");
+						let publish_branch = |branch: &(&str, _, Rc<package::Compiled>),
+						                      publisher: &mut dyn Publisher| {
+							let &(name, branch, ref compiled) = branch;
+							let result = publish::publish(
+								&project, compiled, branch, name, publisher, &retry, resume,
+							);
+							(name, branch, result)
+						};
+						let mut results = Vec::new();
+						match (m.value_of("FAKE"), m.value_of("STEAMCMD")) {
+							(Some(path), _) => {
+								let mut workshop = publisher::Fake::new(path);
+								for branch in &branches {
+									results.push(publish_branch(branch, &mut workshop));
+								}
+							},
+							(None, Some(username)) => {
+								for branch in &branches {
+									let mut steamcmd = steamcmd::SteamCmd {
+										binary:   m
+											.value_of("STEAMCMDPATH")
+											.unwrap_or("steamcmd")
+											.into(),
+										username: username.into(),
+										dir:      project.path.join(".steamcmd").join(branch.0),
+									};
+									results.push(publish_branch(branch, &mut steamcmd));
+								}
+							},
+							(None, None) => {
+								// The SDK is only initialised once per process, see `Steam::with`
+								let app_ids: Vec<_> =
+									branches.iter().map(|b| project.app_id(b.1)).collect();
+								if let Some(i) = app_ids.iter().position(|&a| a != app_ids[0]) {
+									return Err(Error::MixedAppIds(
+										branches[0].0.into(),
+										branches[i].0.into(),
+									));
+								}
+								publisher::Steam::with(app_ids[0], |steam| {
+									for branch in &branches {
+										results.push(publish_branch(branch, steam));
+									}
+								})?;
+							},
+						}
+
+						if results.len() == 1 {
+							results.pop().expect("Could not get result").2?;
+						} else {
+							let mut failed = 0;
+							println!("{:<16} {:<16} result", "branch", "item");
+							for (name, branch, result) in results {
+								let (item, result) = match result {
+									Ok(item) => (format!("{:X}", item), "published".to_owned()),
+									Err(e) => {
+										failed += 1;
+										let item = project
+											.modid(branch, name)
+											.ok()
+											.and_then(|i| i)
+											.map_or("-".to_owned(), |i| format!("{:X}", i));
+										(item, format!("{:?}", Error::from(e)))
+									},
+								};
+								println!("{:<16} {:<16} {}", name, item, result);
+							}
+							if failed > 0 {
+								return Err(Error::PublishFailed(failed));
+							}
+						}
+					}
+				},
+				("check", Some(m)) => {
+					let names: Vec<&str> = match m.values_of("BRANCHES") {
+						Some(branches) => branches.collect(),
+						None => project.config.branch_names(),
+					};
+					let branches = names
+						.iter()
+						.map(|&name| project.config.branch(name))
+						.collect::<Result<Vec<_>, _>>()?;
+					let mut count = 0;
+					for problem in validate::config(&project.path)? {
+						println!("{}", problem);
+						count += 1;
+					}
+					let locations = validate::Locations::read(&project.path);
+					let compiled = package::Compiled::for_branches(&project, &branches)?;
+					for ((&name, branch), compiled) in names.iter().zip(branches).zip(compiled) {
+						let item = project.modid(branch, name)?.unwrap_or(Item::PLACEHOLDER);
+						let artefacts =
+							publish::Artefacts::new(&project, &compiled, branch, name, item)?;
+						for mut problem in check::artefacts(name, branch, &artefacts) {
+							problem.location = locations.get(&problem.field);
+							println!("{}", problem);
+							count += 1;
+						}
+					}
+					if count > 0 {
+						return Err(Error::CheckFailed(count));
+					}
+					println!("No problems found");
+				},
+				("modid", Some(m)) => {
+					let all = || project.config.branch_names();
+					match m.subcommand() {
+						("set", Some(m)) => {
+							let branch = m.value_of("BRANCH").expect("Could not get BRANCH");
+							let item = m.value_of("ITEM").expect("Could not get ITEM");
+							let item: Item = item.parse().map_err(|e| (item.to_owned(), e))?;
+							modid::set(&project, branch, item, m.is_present("CONFIG"))?;
+							println!("{}", modid::Entry::get(&project, branch)?);
+						},
+						("clear", Some(m)) => {
+							let branch = m.value_of("BRANCH").expect("Could not get BRANCH");
+							modid::clear(&project, branch)?;
+							println!("{}", modid::Entry::get(&project, branch)?);
+						},
+						("migrate", Some(m)) => {
+							let branches = m.values_of("BRANCHES").map_or_else(all, |b| b.collect());
+							for branch in branches {
+								if let Some(item) = modid::migrate(&project, branch)? {
+									println!("{}: moved {} into laspad.toml", branch, item);
+								}
+							}
+						},
+						(_, m) => {
+							let branches = m
+								.and_then(|m| m.values_of("BRANCHES"))
+								.map_or_else(all, |b| b.collect());
+							for branch in branches {
+								println!("{}", modid::Entry::get(&project, branch)?);
+							}
+						},
+					}
+				},
+				("export-launchpad", Some(m)) => {
+					let branch = m.value_of("BRANCH").unwrap_or("master");
+					let path = launchpad::export(&project, project.config.branch(branch)?, branch)?;
+					println!("Wrote {}", path.display());
+				},
+				("migrate", Some(m)) => {
+					let migration = migrate::Migration::plan(&project)?;
+					if migration.changes.is_empty() {
+						println!("laspad.toml is already version {}", config::Config::VERSION);
+					} else {
+						print!("{}", migration);
+						if !m.is_present("DRYRUN") {
+							migration.write(&project)?;
+						}
+					}
+				},
+				("branches", _) => {
+					for name in project.config.branch_names() {
+						let branch = project.config.branch(name)?;
+						println!("{}", name);
+						println!("\tname:    {}", branch.name);
+						match project.modid(branch, name)? {
+							Some(item) => println!("\tmod ID:  {}", item),
+							None => println!("\tmod ID:  not published yet"),
+						}
+						println!("\ttags:    {}", branch.tags.join(", "));
+						match &branch.preview {
+							Some(preview) => println!("\tpreview: {}", preview.display()),
+							None => println!("\tpreview: none"),
+						}
+					}
+				},
+				("history", Some(m)) => {
+					let journal = journal::Journal::open(&project)?;
+					let runs = journal.runs(m.value_of("BRANCH"));
+					if runs.is_empty() {
+						println!("Nothing has been published yet");
+					}
+					for run in &runs {
+						println!("{}", journal::Summary(run));
+					}
+				},
+				("changes", Some(m)) => {
 					let branch = m.value_of("BRANCH").unwrap_or("master");
-					publish::publish(&project, &project.config.branches[branch], &branch)?;
+					println!(
+						"{}",
+						changelog::change_note(&project, project.config.branch(branch)?, branch)?
+					);
 				},
 				_ => {
 					unreachable!();
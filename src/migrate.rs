@@ -0,0 +1,425 @@
+use derive_more::{Display, From};
+use erroneous::Error as EError;
+use std::{
+	fmt,
+	fs,
+	io,
+	path::{Path, PathBuf},
+};
+use toml::value::{Table, Value};
+
+use crate::{config::Config, project::Project};
+
+#[derive(Debug, Display, EError, From)]
+pub enum Error {
+	#[display(fmt = "Could not read '{}'", "_0.display()")]
+	Read(PathBuf, #[error(source)] io::Error),
+	#[display(fmt = "Could not write '{}'", "_0.display()")]
+	Write(PathBuf, #[error(source)] io::Error),
+	#[display(fmt = "Could not parse laspad.toml")]
+	Parse(#[error(source)] toml::de::Error),
+	#[display(fmt = "'{}' should be the table of a branch", _0)]
+	NotABranch(String),
+	#[display(fmt = "{} is not a valid version!", _0)]
+	InvalidVersion(i64),
+}
+
+/// The order keys are written in, as in the example configuration. Other keys come last.
+const ORDER: &[&str] = &[
+	"version",
+	"app_id",
+	"source_dir",
+	"output_dir",
+	"dependencies",
+	"name",
+	"tags",
+	"autodescription",
+	"description",
+	"description_str",
+	"preview",
+	"website",
+	"item",
+	"visibility",
+	"changelog",
+	"screenshots",
+	"videos",
+];
+
+/// A file that the migration writes.
+pub struct Change {
+	/// Relative to the project
+	pub path: PathBuf,
+	/// `None` if the file doesn't exist yet
+	pub old:  Option<String>,
+	pub new:  String,
+}
+
+/// The files that converting a project to the latest `laspad.toml` writes.
+#[derive(Default)]
+pub struct Migration {
+	pub changes: Vec<Change>,
+}
+
+fn read(path: &Path) -> Result<Option<String>, Error> {
+	match fs::read_to_string(path) {
+		Ok(s) => Ok(Some(s)),
+		Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+		Err(e) => Err(Error::Read(path.into(), e)),
+	}
+}
+
+fn key(key: &str) -> String {
+	let bare = key
+		.chars()
+		.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+	if bare && !key.is_empty() {
+		key.into()
+	} else {
+		Value::String(key.into()).to_string()
+	}
+}
+
+/// Writes the values of `table` aligned, and then its subtables.
+fn write_table(out: &mut String, header: Option<&str>, table: &Table) {
+	let position = |k: &str| ORDER.iter().position(|&o| o == k).unwrap_or(ORDER.len());
+	let mut values: Vec<_> = table.iter().filter(|(_, v)| !v.is_table()).collect();
+	values.sort_by_key(|(k, _)| position(k));
+
+	if let Some(header) = header.filter(|_| !values.is_empty()) {
+		out.push_str(&format!("\n[{}]\n", header));
+	}
+	let width = values.iter().map(|(k, _)| key(k).len()).max().unwrap_or(0);
+	for (k, v) in values {
+		out.push_str(&format!("{:<2$} = {}\n", key(k), v, width));
+	}
+	for (k, v) in table {
+		if let Value::Table(table) = v {
+			let header = match header {
+				Some(header) => format!("{}.{}", header, key(k)),
+				None => key(k),
+			};
+			write_table(out, Some(&header), table);
+		}
+	}
+}
+
+fn to_toml(config: &Table) -> String {
+	let mut out = "# Indicates the version of laspad's configuration used\n".to_owned();
+	write_table(&mut out, None, config);
+	out
+}
+
+/// Changes the top level `version` of `content`, leaving the rest as it is.
+fn set_version(content: &str) -> String {
+	let mut offset = 0;
+	for line in content.split('\n') {
+		let trimmed = line.trim_start();
+		if trimmed.starts_with('[') {
+			break;
+		}
+		let value = trimmed
+			.strip_prefix("version")
+			.map(|rest| rest.trim_start())
+			.filter(|rest| rest.starts_with('='))
+			.map(|rest| rest[1..].trim_start());
+		if let Some(value) = value {
+			let start = offset + line.len() - value.len();
+			let digits = value.len() - value.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+			let mut content = content.to_owned();
+			content.replace_range(start..start + digits, &Config::VERSION.to_string());
+			return content;
+		}
+		offset += line.len() + 1;
+	}
+	format!("version = {}\n{}", Config::VERSION, content)
+}
+
+/// The lines of `old` and `new` prefixed with ' ', '-' or '+',
+/// following their longest common subsequence.
+fn diff(old: &str, new: &str) -> Vec<String> {
+	let old: Vec<&str> = old.lines().collect();
+	let new: Vec<&str> = new.lines().collect();
+	// `common[i][j]` is the length of the longest common subsequence of `old[i..]` and `new[j..]`
+	let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+	for i in (0..old.len()).rev() {
+		for j in (0..new.len()).rev() {
+			common[i][j] = if old[i] == new[j] {
+				common[i + 1][j + 1] + 1
+			} else {
+				common[i + 1][j].max(common[i][j + 1])
+			};
+		}
+	}
+
+	let (mut i, mut j) = (0, 0);
+	let mut lines = Vec::new();
+	while i < old.len() || j < new.len() {
+		if i < old.len() && j < new.len() && old[i] == new[j] {
+			lines.push(format!(" {}", old[i]));
+			i += 1;
+			j += 1;
+		} else if i < old.len() && (j == new.len() || common[i + 1][j] >= common[i][j + 1]) {
+			lines.push(format!("-{}", old[i]));
+			i += 1;
+		} else {
+			lines.push(format!("+{}", new[j]));
+			j += 1;
+		}
+	}
+	lines
+}
+
+impl Migration {
+	/// Moves the inline description of the branch into a text file.
+	/// Not markdown, which would be converted, since the inline one is published as it is.
+	fn move_description(
+		&mut self,
+		project: &Project,
+		name: &str,
+		branches: usize,
+		branch: &mut Table,
+	) -> Result<(), Error> {
+		// A description file takes precedence over the inline one anyway
+		if branch.contains_key("description") {
+			return Ok(());
+		}
+		let description = match branch.get("description_str") {
+			Some(Value::String(description)) => description.clone(),
+			_ => return Ok(()),
+		};
+		let file = if branches == 1 {
+			"description.txt".to_owned()
+		} else {
+			format!("description.{}.txt", name)
+		};
+		self.changes.push(Change {
+			old:  read(&project.path.join(&file))?,
+			path: file.clone().into(),
+			new:  description,
+		});
+		branch.remove("description_str");
+		branch.insert("description".into(), Value::String(file));
+		Ok(())
+	}
+
+	/// The `laspad.toml` of a Launch Pad project, which only has one branch.
+	fn convert_mod_settings(&mut self, project: &Project) -> Result<String, Error> {
+		let config = &project.config;
+		let mut new = Table::new();
+		new.insert("version".into(), Value::Integer(Config::VERSION));
+		if let Some((source_dir, output_dir)) = &config.source_output_dir {
+			let path = |p: &Path| Value::String(p.to_string_lossy().into_owned());
+			new.insert("source_dir".into(), path(source_dir));
+			new.insert("output_dir".into(), path(output_dir));
+		}
+
+		let mut branches = Table::new();
+		for (name, branch) in &config.branches {
+			let mut table = Table::new();
+			let string = |s: &str| Value::String(s.into());
+			table.insert("name".into(), string(&branch.name));
+			let tags = branch.tags.iter().map(|t| string(t)).collect();
+			table.insert("tags".into(), Value::Array(tags));
+			if let Some(autodescription) = branch.autodescription {
+				table.insert("autodescription".into(), Value::Boolean(autodescription));
+			}
+			if let Some(description) = &branch.description_str {
+				table.insert("description_str".into(), string(description));
+			}
+			if let Some(preview) = &branch.preview {
+				table.insert("preview".into(), string(&preview.to_string_lossy()));
+			}
+			if let Some(item) = branch.item {
+				table.insert("item".into(), string(&format!("{:X}", item)));
+			}
+			self.move_description(project, name, config.branches.len(), &mut table)?;
+			branches.insert(name.clone(), Value::Table(table));
+		}
+		new.insert("branch".into(), Value::Table(branches));
+		Ok(to_toml(&new))
+	}
+
+	/// Version 0 has the branches at the top level.
+	fn convert_v0(&mut self, project: &Project, mut config: Table) -> Result<String, Error> {
+		config.remove("version");
+		let count = config.len();
+		let mut branches = Table::new();
+		for (name, branch) in config {
+			let mut branch = match branch {
+				Value::Table(branch) => branch,
+				_ => return Err(Error::NotABranch(name)),
+			};
+			self.move_description(project, &name, count, &mut branch)?;
+			branches.insert(name, Value::Table(branch));
+		}
+
+		let mut new = Table::new();
+		new.insert("version".into(), Value::Integer(Config::VERSION));
+		new.insert("branch".into(), Value::Table(branches));
+		Ok(to_toml(&new))
+	}
+
+	/// Plans the conversion of the project's `mod.settings`, if it has no `laspad.toml`,
+	/// or of its older `laspad.toml` to the latest version.
+	/// There are no changes if it is up to date.
+	pub fn plan(project: &Project) -> Result<Self, Error> {
+		let mut migration = Migration::default();
+		let path = PathBuf::from("laspad.toml");
+		let old = read(&project.path.join(&path))?;
+		let new = match &old {
+			None => migration.convert_mod_settings(project)?,
+			Some(content) => {
+				let config: Table = toml::from_str(content)?;
+				match config.get("version").and_then(|v| v.as_integer()) {
+					None | Some(0) => migration.convert_v0(project, config)?,
+					Some(v) if v == Config::VERSION => return Ok(migration),
+					Some(v) if v > 0 && v < Config::VERSION => set_version(content),
+					Some(v) => return Err(Error::InvalidVersion(v)),
+				}
+			},
+		};
+		migration.changes.insert(0, Change { path, old, new });
+		Ok(migration)
+	}
+
+	pub fn write(&self, project: &Project) -> Result<(), Error> {
+		for change in &self.changes {
+			let path = project.path.join(&change.path);
+			fs::write(&path, &change.new).map_err(|e| Error::Write(path, e))?;
+		}
+		Ok(())
+	}
+}
+
+impl fmt::Display for Migration {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		for change in &self.changes {
+			match change.old {
+				Some(_) => writeln!(f, "--- {}", change.path.display())?,
+				None => writeln!(f, "--- /dev/null")?,
+			}
+			writeln!(f, "+++ {}", change.path.display())?;
+			let old = change.old.as_ref().map_or("", |s| s.as_str());
+			for line in diff(old, &change.new) {
+				writeln!(f, "{}", line)?;
+			}
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+	assert_eq!(diff("a\nb\nc\n", "a\nc\nd\n"), vec![" a", "-b", " c", "+d"]);
+	assert_eq!(
+		set_version("# laspad\nversion = 1 # old\n[branch.master]\nversion = 1\n"),
+		"# laspad\nversion = 2 # old\n[branch.master]\nversion = 1\n"
+	);
+
+	let root = crate::util::TempDir::new("migrate");
+	fs::write(
+		root.join("mod.settings"),
+		r#"name        = "Test"
+source_dir  = "source"
+output_dir  = "output"
+description = "A [b]mod[/b]\nwith *two* lines"
+image       = "preview.jpg"
+tag_modtype = "Gameplay Tweak"
+publish_id  = "1a"
+"#,
+	)
+	.unwrap();
+	let project = Project::get(&root).unwrap().unwrap();
+	let item = "1A".parse().unwrap();
+	let description = |project: &Project, branch: &str| {
+		let branch = project.config.branch(branch).unwrap();
+		branch.description(project, item).unwrap()
+	};
+	let before = description(&project, "master");
+	let migration = Migration::plan(&project).unwrap();
+	assert_eq!(
+		migration.changes[0].new,
+		r#"# Indicates the version of laspad's configuration used
+version    = 2
+source_dir = "source"
+output_dir = "output"
+
+[branch.master]
+name            = "Test"
+tags            = ["Gameplay Tweak"]
+autodescription = false
+description     = "description.txt"
+preview         = "preview.jpg"
+item            = "1A"
+"#
+	);
+	assert!(migration
+		.to_string()
+		.starts_with("--- /dev/null\n+++ laspad.toml\n+# Indicates"));
+	migration.write(&project).unwrap();
+	assert_eq!(
+		fs::read_to_string(root.join("description.txt")).unwrap(),
+		"A [b]mod[/b]\nwith *two* lines"
+	);
+
+	let project = Project::get(&root).unwrap().unwrap();
+	let master = project.config.branch("master").unwrap();
+	assert_eq!(master.item, Some("1A".parse().unwrap()));
+	assert_eq!(master.description, Some("description.txt".into()));
+	assert_eq!(description(&project, "master"), before);
+	assert_eq!(
+		project.config.source_output_dir,
+		Some(("source".into(), "output".into()))
+	);
+	assert!(Migration::plan(&project).unwrap().changes.is_empty());
+
+	let project = Project::write(
+		&root,
+		r#"[master]
+name            = "Test"
+tags            = []
+description_str = "[b]Master[/b]\n* not a list"
+[master.guard]
+tag = true
+[beta]
+name = "Test beta"
+tags = []
+description_str = "Beta"
+"#,
+	);
+	let before = description(&project, "master");
+	let migration = Migration::plan(&project).unwrap();
+	assert_eq!(
+		migration.changes[0].new,
+		r#"# Indicates the version of laspad's configuration used
+version = 2
+
+[branch.beta]
+name        = "Test beta"
+tags        = []
+description = "description.beta.txt"
+
+[branch.master]
+name        = "Test"
+tags        = []
+description = "description.master.txt"
+
+[branch.master.guard]
+tag = true
+"#
+	);
+	let files: Vec<_> = migration.changes.iter().map(|c| c.path.clone()).collect();
+	assert_eq!(
+		files,
+		vec![
+			PathBuf::from("laspad.toml"),
+			"description.beta.txt".into(),
+			"description.master.txt".into(),
+		]
+	);
+	migration.write(&project).unwrap();
+	let project = Project::get(&root).unwrap().unwrap();
+	assert!(project.config.branch("master").unwrap().guard.is_some());
+	assert_eq!(description(&project, "master"), before);
+}
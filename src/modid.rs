@@ -0,0 +1,267 @@
+use derive_more::{Display, From};
+use erroneous::Error as EError;
+use std::{fmt, fs, io, path::PathBuf};
+
+use crate::{
+	config,
+	item::Item,
+	project::{self, Project},
+	scan::{self, code_end, Kind},
+	util,
+};
+
+#[derive(Debug, Display, EError, From)]
+pub enum Error {
+	#[display(fmt = "Could not get Mod ID")]
+	ModID(#[error(source)] project::ModIDError),
+	#[display(fmt = "{}", _0)]
+	ReadError(#[error(defer)] util::ReadError),
+	#[display(fmt = "Could not write '{}'", "_0.display()")]
+	Write(PathBuf, #[error(source)] io::Error),
+	#[display(fmt = "Could not remove '{}'", "_0.display()")]
+	Remove(PathBuf, #[error(source)] io::Error),
+	#[display(fmt = "The project has no laspad.toml to move the Mod ID into")]
+	NoConfig,
+	#[display(fmt = "Could not find the table of branch '{}' in laspad.toml", _0)]
+	NoBranchTable(String),
+	#[display(fmt = "{}", _0)]
+	UnknownBranch(#[error(defer)] config::UnknownBranch),
+}
+
+/// Where the Mod ID of a branch comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+	/// `item` in `laspad.toml`
+	Config,
+	/// `.modid.<branch>`
+	File,
+}
+
+/// The Mod ID of a branch, as shown by `laspad modid`.
+pub struct Entry<'a> {
+	pub branch: &'a str,
+	pub item:   Option<(Item, Source)>,
+}
+
+impl<'a> Entry<'a> {
+	pub fn get(project: &Project, branch_name: &'a str) -> Result<Self, Error> {
+		let branch = project.config.branch(branch_name)?;
+		let source = if branch.item.is_some() {
+			Source::Config
+		} else {
+			Source::File
+		};
+		Ok(Entry {
+			branch: branch_name,
+			item:   project.modid(branch, branch_name)?.map(|i| (i, source)),
+		})
+	}
+}
+
+impl<'a> fmt::Display for Entry<'a> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self.item {
+			Some((item, Source::Config)) => {
+				write!(f, "{}: {} from laspad.toml", self.branch, item)
+			},
+			Some((item, Source::File)) => write!(
+				f,
+				"{}: {} from .modid.{}, `laspad modid migrate {}` moves it into laspad.toml",
+				self.branch, item, self.branch, self.branch
+			),
+			None => write!(f, "{}: not published yet", self.branch),
+		}
+	}
+}
+
+fn remove_file(project: &Project, branch_name: &str) -> Result<(), Error> {
+	let path = project.modid_path(branch_name);
+	if path.exists() {
+		fs::remove_file(&path).map_err(|e| Error::Remove(path, e))?;
+	}
+	Ok(())
+}
+
+/// Sets or removes `item` in the table of the branch in `content`,
+/// leaving the rest of it as it is.
+fn set_item(content: &str, branch_name: &str, item: Option<Item>) -> Result<String, Error> {
+	let mut lines: Vec<String> = content.lines().map(String::from).collect();
+	let scanned = scan::lines(&lines);
+
+	// Version 0 has the branches at the top level
+	let start = [vec!["branch", branch_name], vec![branch_name]]
+		.iter()
+		.find_map(|path| scanned.iter().position(|l| l.is(Kind::Header, path)))
+		.ok_or_else(|| Error::NoBranchTable(branch_name.into()))?;
+	let mut path: Vec<&str> = scanned[start].path.iter().map(String::as_str).collect();
+	path.push("item");
+	let start = start + 1;
+	let end = scanned[start..]
+		.iter()
+		.position(|l| l.kind == Kind::Header)
+		.map_or(lines.len(), |i| start + i);
+	let key = (start..end).find(|&i| scanned[i].is(Kind::Key, &path));
+
+	match (key, item) {
+		(Some(i), Some(item)) => {
+			let line = &lines[i];
+			let eq = line.find('=').expect("Could not find '=' in line");
+			let comment = &line[code_end(line)..];
+			let mut new = format!("{} \"{:X}\"", &line[..=eq], item);
+			if !comment.is_empty() {
+				new = format!("{} {}", new, comment);
+			}
+			lines[i] = new;
+		},
+		(Some(i), None) => {
+			lines.remove(i);
+		},
+		(None, Some(item)) => {
+			let at = (start..end)
+				.rev()
+				.find(|&i| !lines[i].trim().is_empty())
+				.map_or(start, |i| i + 1);
+			lines.insert(at, format!("item = \"{:X}\"", item));
+		},
+		(None, None) => (),
+	}
+
+	Ok(scan::join(&lines, content))
+}
+
+/// Sets or removes `item` in the table of the branch in `laspad.toml`.
+fn set_in_config(project: &Project, branch_name: &str, item: Option<Item>) -> Result<(), Error> {
+	let path = project.path.join("laspad.toml");
+	if !path.exists() {
+		return Err(Error::NoConfig);
+	}
+	let content = set_item(&util::read_to_string(&path)?, branch_name, item)?;
+	fs::write(&path, content).map_err(|e| Error::Write(path, e))
+}
+
+/// Sets the Mod ID of the branch, in `laspad.toml` if `config` or if it is already there,
+/// otherwise in `.modid.<branch>`.
+pub fn set(project: &Project, branch_name: &str, item: Item, config: bool) -> Result<(), Error> {
+	if config || project.config.branch(branch_name)?.item.is_some() {
+		set_in_config(project, branch_name, Some(item))?;
+		remove_file(project, branch_name)
+	} else {
+		let path = project.modid_path(branch_name);
+		fs::write(&path, format!("{:X}", item)).map_err(|e| Error::Write(path, e))
+	}
+}
+
+/// Forgets the Mod ID of the branch, so that the next publish creates a new workshop item.
+pub fn clear(project: &Project, branch_name: &str) -> Result<(), Error> {
+	if project.config.branch(branch_name)?.item.is_some() {
+		set_in_config(project, branch_name, None)?;
+	}
+	remove_file(project, branch_name)
+}
+
+/// Moves the Mod ID of the branch from `.modid.<branch>` into `laspad.toml`.
+/// Returns the Mod ID if there was one to move.
+pub fn migrate(project: &Project, branch_name: &str) -> Result<Option<Item>, Error> {
+	match Entry::get(project, branch_name)?.item {
+		Some((item, Source::File)) => {
+			set(project, branch_name, item, true)?;
+			Ok(Some(item))
+		},
+		_ => Ok(None),
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+	let root = crate::util::TempDir::new("modid");
+	Project::write(
+		&root,
+		r#"version = 1
+
+[branch.master]
+name = "Test" # the title
+tags = []
+
+[branch.master.guard]
+dirty = "ignore"
+
+[branch.beta]
+name = "Test beta"
+tags = []
+item = "1A"
+"#,
+	);
+	let project = || Project::get(&root).unwrap().unwrap();
+
+	set(&project(), "master", "ff".parse().unwrap(), false).unwrap();
+	assert_eq!(
+		fs::read_to_string(root.join(".modid.master")).unwrap(),
+		"FF"
+	);
+	assert_eq!(
+		Entry::get(&project(), "master").unwrap().item,
+		Some(("FF".parse().unwrap(), Source::File))
+	);
+
+	assert_eq!(
+		migrate(&project(), "master").unwrap(),
+		Some("FF".parse().unwrap())
+	);
+	assert!(!root.join(".modid.master").exists());
+	assert_eq!(migrate(&project(), "master").unwrap(), None);
+
+	set(
+		&project(),
+		"beta",
+		"https://steamcommunity.com/sharedfiles/filedetails/?id=43".parse().unwrap(),
+		false,
+	)
+	.unwrap();
+	clear(&project(), "master").unwrap();
+	assert_eq!(
+		fs::read_to_string(root.join("laspad.toml")).unwrap(),
+		r#"version = 1
+
+[branch.master]
+name = "Test" # the title
+tags = []
+
+[branch.master.guard]
+dirty = "ignore"
+
+[branch.beta]
+name = "Test beta"
+tags = []
+item = "2B"
+"#
+	);
+	assert_eq!(Entry::get(&project(), "master").unwrap().item, None);
+
+	let template = include_str!("../assets/laspad.toml");
+	let set = set_item(template, "master", "1A".parse().ok()).unwrap();
+	assert_eq!(
+		set,
+		template.replace(
+			"# IDs of YouTube videos to show on the workshop page\n",
+			"# IDs of YouTube videos to show on the workshop page\nitem = \"1A\"\n"
+		)
+	);
+	let set = set_item(&set, "master", "2B".parse().ok()).unwrap();
+	assert!(set.contains("\nitem = \"2B\"\n"));
+	assert_eq!(set_item(&set, "master", None).unwrap(), template);
+	assert!(set_item(template, "beta", None).is_err());
+	let crlf = template.replace('\n', "\r\n");
+	assert_eq!(
+		set_item(&crlf, "master", "1A".parse().ok()).unwrap(),
+		set_item(template, "master", "1A".parse().ok())
+			.unwrap()
+			.replace('\n', "\r\n")
+	);
+
+	let quoted = "[branch.\"a.b\"] # the branch\n\"item\" = \"1A\" # the Mod ID\n[branch.c]\n";
+	assert_eq!(
+		set_item(quoted, "a.b", "2B".parse().ok()).unwrap(),
+		"[branch.\"a.b\"] # the branch\n\"item\" = \"2B\" # the Mod ID\n[branch.c]\n"
+	);
+}
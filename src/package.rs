@@ -1,9 +1,11 @@
 use derive_more::{Display, From};
 use erroneous::Error as EError;
 use std::{
+	collections::HashMap,
 	fs,
 	io::{self, Seek, Write},
-	path::Path,
+	path::{Path, PathBuf},
+	rc::Rc,
 };
 use zip::{result::ZipError, write::ZipWriter};
 
@@ -46,7 +48,77 @@ impl<W: Write + Seek> compile::Out for ZipTarget<W> {
 	}
 }
 
+/// The files of a compiled project, as pairs of source and destination.
+/// A file from a later source, like an overlay, replaces the one at the same destination.
+/// Branches with the same sources can be packaged from it without compiling the project again.
+pub struct Compiled {
+	files: Vec<(PathBuf, PathBuf)>,
+	index: HashMap<PathBuf, usize>,
+}
+
+impl compile::Out for Compiled {
+	fn file(&mut self, src: &Path, dst: &Path) -> Result<(), io::Error> {
+		match self.index.get(dst) {
+			Some(&i) => self.files[i].0 = src.into(),
+			None => {
+				self.index.insert(dst.into(), self.files.len());
+				self.files.push((src.into(), dst.into()));
+			},
+		}
+		Ok(())
+	}
+
+	fn dir(&mut self, _: &Path) -> Result<(), io::Error> {
+		Ok(())
+	}
+}
+
+impl Compiled {
+	pub fn new(project: &Project, branch: &Branch) -> Result<Self, Error> {
+		let mut compiled = Compiled {
+			files: Vec::new(),
+			index: HashMap::new(),
+		};
+		compile::compile(project, Some(branch), &mut compiled)?;
+		Ok(compiled)
+	}
+
+	/// Compiles the project for each of the branches,
+	/// only once for branches with the same dependencies and overlays.
+	pub fn for_branches(project: &Project, branches: &[&Branch]) -> Result<Vec<Rc<Self>>, Error> {
+		let mut compiled: Vec<(_, Rc<Self>)> = Vec::new();
+		let mut result = Vec::new();
+		for &branch in branches {
+			let deps: Vec<_> = project
+				.dependencies(Some(branch))
+				.map_err(compile::Error::Dependencies)?
+				.into_iter()
+				.map(|d| d.path)
+				.collect();
+			let sources = (deps, branch.overlays.clone());
+			let shared = match compiled.iter().find(|(s, _)| *s == sources) {
+				Some((_, c)) => c.clone(),
+				None => {
+					let c = Rc::new(Compiled::new(project, branch)?);
+					compiled.push((sources, c.clone()));
+					c
+				},
+			};
+			result.push(shared);
+		}
+		Ok(result)
+	}
+}
+
 pub fn package<T: Write + Seek>(project: &Project, branch: &Branch, out: T) -> Result<T, Error> {
+	package_compiled(&Compiled::new(project, branch)?, branch, out)
+}
+
+pub fn package_compiled<T: Write + Seek>(
+	compiled: &Compiled,
+	branch: &Branch,
+	out: T,
+) -> Result<T, Error> {
 	let mut writer = ZipWriter::new(out);
 	let options =
 		zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
@@ -56,7 +128,68 @@ pub fn package<T: Write + Seek>(project: &Project, branch: &Branch, out: T) -> R
 		.map_err(ZipError::Io)?;
 
 	let mut target = ZipTarget { writer };
-	compile::compile(project, &mut target)?;
+	for (src, dst) in &compiled.files {
+		compile::Out::file(&mut target, src, dst)
+			.map_err(|e| compile::Error::Create(dst.clone(), e))?;
+	}
 
 	Ok(target.writer.finish()?)
 }
+
+#[cfg(test)]
+#[test]
+fn test() {
+	let root = crate::util::TempDir::new("package");
+	fs::create_dir_all(root.join("src/lua")).unwrap();
+	fs::create_dir_all(root.join("experimental/lua")).unwrap();
+	fs::write(root.join("src/lua/a.lua"), "a").unwrap();
+	fs::write(root.join("src/lua/b.lua"), "b").unwrap();
+	fs::write(root.join("experimental/lua/b.lua"), "experimental b").unwrap();
+	let project = Project::write(
+		&root,
+		r#"
+version = 2
+[defaults]
+tags = []
+[branch.master]
+name = "Test"
+[branch.stable]
+name = "Test stable"
+[branch.beta]
+name     = "Test beta"
+overlays = ["experimental"]
+"#,
+	);
+	let branches: Vec<_> = ["master", "stable", "beta"]
+		.iter()
+		.map(|&name| project.config.branch(name).unwrap())
+		.collect();
+
+	let compiled = Compiled::for_branches(&project, &branches).unwrap();
+	assert!(Rc::ptr_eq(&compiled[0], &compiled[1]));
+	assert!(!Rc::ptr_eq(&compiled[0], &compiled[2]));
+	let files = |c: &Compiled| {
+		let mut files: Vec<_> = c
+			.files
+			.iter()
+			.map(|(src, dst)| (fs::read_to_string(src).unwrap(), dst.clone()))
+			.collect();
+		files.sort();
+		files
+	};
+	assert_eq!(
+		files(&compiled[2]),
+		vec![
+			("a".to_owned(), PathBuf::from("lua/a.lua")),
+			("experimental b".to_owned(), PathBuf::from("lua/b.lua")),
+		]
+	);
+	assert_eq!(files(&compiled[0])[1].0, "b");
+
+	let zip = package(&project, branches[2], io::Cursor::new(Vec::new())).unwrap();
+	let mut zip = zip::ZipArchive::new(zip).unwrap();
+	assert_eq!(zip.len(), 3);
+	let mut b = String::new();
+	io::Read::read_to_string(&mut zip.by_name("lua/b.lua").unwrap(), &mut b).unwrap();
+	assert_eq!(b, "experimental b");
+}
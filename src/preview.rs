@@ -0,0 +1,140 @@
+use image::{DynamicImage, GenericImageView, ImageOutputFormat};
+use std::fmt;
+
+/// The largest preview image the workshop accepts
+pub const MAX_SIZE: usize = 1024 * 1024;
+
+/// The JPEG quality oversized images are re-encoded with
+const JPEG_QUALITY: u8 = 90;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+	Png,
+	Jpeg,
+	Gif,
+}
+
+impl Format {
+	/// Detects the format of an image from its magic bytes.
+	pub fn detect(data: &[u8]) -> Option<Format> {
+		if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+			Some(Format::Png)
+		} else if data.starts_with(b"\xff\xd8\xff") {
+			Some(Format::Jpeg)
+		} else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+			Some(Format::Gif)
+		} else {
+			None
+		}
+	}
+}
+
+impl fmt::Display for Format {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			Format::Png => "PNG",
+			Format::Jpeg => "JPEG",
+			Format::Gif => "GIF",
+		})
+	}
+}
+
+/// What `fit` did to an image.
+pub struct Fitted {
+	pub data: Vec<u8>,
+	/// The format, size and dimensions of the original image
+	pub from: (Option<Format>, usize, (u32, u32)),
+	/// The format and dimensions of the new image
+	pub to:   (Format, (u32, u32)),
+}
+
+impl fmt::Display for Fitted {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let (format, size, (width, height)) = self.from;
+		match format {
+			Some(format) => write!(f, "re-encoded the {} KiB {}", size / 1024, format)?,
+			None => write!(f, "re-encoded the {} KiB image", size / 1024)?,
+		}
+		write!(f, " as a {} KiB {}", self.data.len() / 1024, self.to.0)?;
+		if self.to.1 != (width, height) {
+			write!(
+				f,
+				", downscaled from {}x{} to {}x{}",
+				width, height, (self.to.1).0, (self.to.1).1
+			)?;
+		}
+		Ok(())
+	}
+}
+
+fn encode(image: &DynamicImage, format: Format) -> Vec<u8> {
+	let mut data = Vec::new();
+	image
+		.write_to(
+			&mut data,
+			match format {
+				Format::Png => ImageOutputFormat::PNG,
+				_ => ImageOutputFormat::JPEG(JPEG_QUALITY),
+			},
+		)
+		.expect("Could not encode image into memory");
+	data
+}
+
+/// Makes an image acceptable for the workshop, re-encoding it if it isn't a PNG, JPEG or GIF
+/// or if it is larger than `MAX_SIZE`, and downscaling it until it fits if that isn't enough.
+/// Returns `None` if the image is fine as it is or can't be decoded, in which case
+/// `check::artefacts` reports it.
+pub fn fit(data: &[u8]) -> Option<Fitted> {
+	let format = Format::detect(data);
+	if format.is_some() && data.len() <= MAX_SIZE {
+		return None;
+	}
+	let mut image = image::load_from_memory(data).ok()?;
+	let dimensions = image.dimensions();
+
+	// PNG keeps transparency and small PNGs stay small, photos are better off as JPEG
+	let target = match format {
+		Some(Format::Png) | None if data.len() <= MAX_SIZE => Format::Png,
+		_ => Format::Jpeg,
+	};
+	let mut encoded = encode(&image, target);
+	while encoded.len() > MAX_SIZE && image.width() > 1 && image.height() > 1 {
+		image = image.resize(
+			image.width() * 3 / 4,
+			image.height() * 3 / 4,
+			image::FilterType::Triangle,
+		);
+		encoded = encode(&image, target);
+	}
+
+	Some(Fitted {
+		data: encoded,
+		from: (format, data.len(), dimensions),
+		to:   (target, image.dimensions()),
+	})
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+	let empty = include_bytes!("../assets/empty.png");
+	assert_eq!(Format::detect(empty), Some(Format::Png));
+	assert!(fit(empty).is_none());
+	assert!(fit(b"not an image").is_none());
+
+	// A large noisy image, which doesn't compress well
+	let mut state = 0x2545_f491u32;
+	let noise = image::RgbImage::from_fn(1200, 1200, |_, _| {
+		state ^= state << 13;
+		state ^= state >> 17;
+		state ^= state << 5;
+		image::Rgb([state as u8, (state >> 8) as u8, (state >> 16) as u8])
+	});
+	let png = encode(&DynamicImage::ImageRgb8(noise), Format::Png);
+	assert!(png.len() > MAX_SIZE);
+	let fitted = fit(&png).unwrap();
+	assert!(fitted.data.len() <= MAX_SIZE);
+	assert_eq!(Format::detect(&fitted.data), Some(Format::Jpeg));
+	assert_eq!(fitted.from.2, (1200, 1200));
+}
@@ -3,6 +3,7 @@ use erroneous::Error as EError;
 use git2::{Oid, Repository};
 use joinery::Joinable;
 use serde_derive::Deserialize;
+use sha2::{Digest, Sha256};
 use std::{
 	fs::{self, File, OpenOptions},
 	io::{self, Write},
@@ -10,9 +11,11 @@ use std::{
 };
 
 use crate::{
-	config::{self, Config},
+	config::{self, Branch, Config, Dep},
 	download,
 	item::Item,
+	journal::Journal,
+	lock::{self, Lock},
 	util,
 };
 
@@ -45,12 +48,33 @@ pub enum UpdateError {
 	DownloadError(Item, #[error(source)] download::Error),
 	#[display(fmt = "The workshop item {} is not a dependency of this project!", _0)]
 	NotFound(Item),
+	#[display(fmt = "Could not update the git dependency '{}'", _0)]
+	Git(String, #[error(source)] lock::Error),
+	#[display(fmt = "Could not update laspad.lock")]
+	LockFile(#[error(source)] lock::Error),
+	#[display(fmt = "The dependency '{}' is not a directory", "_0.display()")]
+	MissingPath(PathBuf),
+}
+
+#[derive(Debug, Display, EError, From)]
+pub enum ModIDError {
+	#[display(fmt = "{}", _0)]
+	ReadError(#[error(defer)] util::ReadError),
+	#[display(
+		fmt = "'.modid.{}' does not have a valid format! It should contain the Mod ID of the branch.",
+		_0
+	)]
+	InvalidFormat(String),
 }
 
 #[derive(Clone, From)]
 pub struct Dependency {
 	pub item: Option<Item>,
 	pub path: PathBuf,
+	/// The URL of the repository a git dependency is cloned from
+	pub git:  Option<String>,
+	/// The commit a git dependency is checked out at
+	pub rev:  Option<Oid>,
 }
 
 impl Dependency {
@@ -81,6 +105,8 @@ impl Dependency {
 	pub fn url(&self) -> Option<String> {
 		if let Some(item) = self.item {
 			Some(item.url())
+		} else if let Some(git) = &self.git {
+			Some(git.clone())
 		} else {
 			let repo = Repository::open(&self.path).ok();
 			let origin = repo.as_ref().and_then(|r| r.find_remote("origin").ok());
@@ -93,7 +119,17 @@ impl Dependency {
 impl Project {
 	pub const DEPENDENCIES_PATH: &'static str = "dependencies";
 	pub const DEPENDENCIES_STEAM_PATH: &'static str = ".dependencies_steam";
+	pub const DEPENDENCIES_GIT_PATH: &'static str = ".dependencies_git";
 	pub const SOURCE_PATH: &'static str = "src";
+	/// What laspad writes into a project that doesn't belong into its git repository
+	pub const GENERATED_PATHS: &'static [&'static str] = &[
+		"compiled",
+		"dry-run",
+		".steamcmd",
+		Journal::PATH,
+		Project::DEPENDENCIES_STEAM_PATH,
+		Project::DEPENDENCIES_GIT_PATH,
+	];
 
 	pub fn src(&self) -> PathBuf {
 		self.path.join(Project::SOURCE_PATH)
@@ -106,6 +142,15 @@ impl Project {
 		Ok(config.map(|config| Project { config, path }))
 	}
 
+	/// Writes `config` as the laspad.toml of a project in `path` and loads it, for tests.
+	#[cfg(test)]
+	pub fn write(path: impl AsRef<Path>, config: impl AsRef<[u8]>) -> Self {
+		let path = path.as_ref();
+		fs::create_dir_all(path).unwrap();
+		fs::write(path.join("laspad.toml"), config).unwrap();
+		Project::get(path).unwrap().unwrap()
+	}
+
 	pub fn new(path: impl AsRef<Path>) -> Result<Self, NewError> {
 		let path = path.as_ref();
 
@@ -119,7 +164,7 @@ impl Project {
 
 		fs::create_dir_all(path.join(Project::SOURCE_PATH)).map_err(NewError::SrcCreation)?;
 
-		let gitignore = ["compiled", Project::DEPENDENCIES_STEAM_PATH]
+		let gitignore = Project::GENERATED_PATHS
 			.iter()
 			.map(|s| format!("/{}\n", s))
 			.join_concat()
@@ -145,27 +190,155 @@ impl Project {
 		path
 	}
 
-	pub fn update(&self, i: Vec<Item>) -> Result<(), UpdateError> {
+	/// Where the git dependency at `url` is cloned to,
+	/// named after the repository and a hash of the URL to tell forks apart.
+	pub fn path_for_git(&self, url: &str) -> PathBuf {
+		let hash = format!("{:x}", Sha256::digest(url.as_bytes()));
+		let name = url
+			.trim_end_matches('/')
+			.rsplit(|c| c == '/' || c == ':')
+			.next()
+			.unwrap_or(url);
+		let name = name.trim_end_matches(".git");
+		let mut path = self.path.join(Project::DEPENDENCIES_GIT_PATH);
+		path.push(&hash[..8]);
+		path.push(name);
+		path
+	}
+
+	pub fn modid_path(&self, branch_name: &str) -> PathBuf {
+		self.path.join(format!(".modid.{}", branch_name))
+	}
+
+	/// The Mod ID of the branch, either from the configuration or from `.modid.<branch>`.
+	/// `None` means the branch has not been published yet.
+	pub fn modid(&self, branch: &Branch, branch_name: &str) -> Result<Option<Item>, ModIDError> {
+		if let Some(item) = branch.item {
+			return Ok(Some(item));
+		}
+
+		let path = self.modid_path(branch_name);
+		if !path.exists() {
+			return Ok(None);
+		}
+
+		util::read_to_string(path)?
+			.trim()
+			.parse()
+			.map(Some)
+			.map_err(|_| ModIDError::InvalidFormat(branch_name.into()))
+	}
+
+	/// The app ID the branch is published for, the one of Natural Selection 2 by default.
+	pub fn app_id(&self, branch: &Branch) -> u32 {
+		branch
+			.app_id
+			.or(self.config.app_id)
+			.unwrap_or(config::NS2_APP_ID)
+	}
+
+	/// Downloads the workshop items and fetches the git repositories among `deps`.
+	/// Git dependencies without a `rev` stay at the revision in `laspad.lock` unless `newest`.
+	pub fn update(&self, deps: Vec<Dep>, newest: bool) -> Result<(), UpdateError> {
 		use self::UpdateError::*;
 		use rayon::prelude::*;
 
-		i.into_par_iter().try_for_each(|item| {
-			if !self.config.deps.contains(&item) {
+		let all = self.config.all_deps();
+		let items: Vec<Item> = deps
+			.iter()
+			.filter_map(|d| match d {
+				Dep::Workshop(item) => Some(*item),
+				_ => None,
+			})
+			.collect();
+		items.into_par_iter().try_for_each(|item| {
+			if !all.contains(&Dep::Workshop(item)) {
 				return Err(NotFound(item));
 			}
 
 			download::download(item, self.path_for_item(item)).map_err(|e| DownloadError(item, e))
 		})?;
+
+		let mut lock = Lock::read(self).map_err(LockFile)?;
+		let mut fetched = false;
+		for dep in &deps {
+			match dep {
+				Dep::Git { git, rev, .. } => {
+					let locked = if newest { None } else { lock.get(git) };
+					let rev = rev.as_ref().map(String::as_str).or(locked);
+					let oid = lock::fetch(git, rev, &self.path_for_git(git))
+						.map_err(|e| Git(git.clone(), e))?;
+					match lock.get(git) {
+						Some(old) if old != oid.to_string() => {
+							info!("Updated {} from {} to {}", git, old, oid)
+						},
+						Some(_) => (),
+						None => info!("Fetched {} at {}", git, oid),
+					}
+					lock.set(git, oid);
+					fetched = true;
+				},
+				Dep::Path { path } => {
+					if !self.path.join(path).is_dir() {
+						return Err(MissingPath(path.clone()));
+					}
+				},
+				Dep::Workshop(_) => (),
+			}
+		}
+		if fetched {
+			// Forget the dependencies that have been removed from laspad.toml
+			lock.dependencies.retain(|l| {
+				all.iter().any(|d| match d {
+					Dep::Git { git, .. } => *git == l.git,
+					_ => false,
+				})
+			});
+			lock.write(self).map_err(LockFile)?;
+		}
 		Ok(())
 	}
 
-	pub fn dependencies(&self) -> Result<Vec<Dependency>, io::Error> {
-		fn dependencies_steam<'a>(this: &'a Project) -> impl Iterator<Item = Dependency> + 'a {
-			this.config.deps.iter().map(move |&item| Dependency {
+	fn dependency(&self, dep: &Dep) -> Dependency {
+		match dep {
+			&Dep::Workshop(item) => Dependency {
 				item: Some(item),
-				path: this.path_for_item(item),
-			})
+				path: self.path_for_item(item),
+				git:  None,
+				rev:  None,
+			},
+			Dep::Git { git, subdir, .. } => {
+				let clone = self.path_for_git(git);
+				let repo = Repository::open(&clone).ok();
+				let rev = repo.as_ref().and_then(|r| r.head().ok()).and_then(|h| h.target());
+				Dependency {
+					item: None,
+					path: subdir.as_ref().map_or(clone.clone(), |s| clone.join(s)),
+					git: Some(git.clone()),
+					rev,
+				}
+			},
+			Dep::Path { path } => Dependency {
+				item: None,
+				path: self.path.join(path),
+				git:  None,
+				rev:  None,
+			},
 		}
+	}
+
+	/// The dependencies shipped with the branch, or with every branch if `None`:
+	/// those in `dependencies/`, then the ones declared in the configuration.
+	pub fn dependencies(&self, branch: Option<&Branch>) -> Result<Vec<Dependency>, io::Error> {
+		let removed = branch.and_then(|b| b.remove_dependencies.as_ref());
+		let added = branch.and_then(|b| b.dependencies.as_ref());
+		let declared = self
+			.config
+			.deps
+			.iter()
+			.filter(|dep| removed.map_or(true, |r| !r.contains(dep)))
+			.chain(added.into_iter().flatten().filter(|dep| !self.config.deps.contains(dep)))
+			.map(|dep| self.dependency(dep));
 
 		let deps = self.path.join(Project::DEPENDENCIES_PATH);
 		if deps.exists() {
@@ -174,12 +347,14 @@ impl Project {
 					Ok(Dependency {
 						item: None,
 						path: d?.path(),
+						git:  None,
+						rev:  None,
 					})
 				})
-				.chain(dependencies_steam(self).map(Ok))
+				.chain(declared.map(Ok))
 				.collect()
 		} else {
-			dependencies_steam(self).map(Ok).collect()
+			Ok(declared.collect())
 		}
 	}
 
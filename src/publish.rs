@@ -1,190 +1,616 @@
-use const_cstr::const_cstr;
 use derive_more::{Display, From};
 use erroneous::Error as EError;
-use futures::Future;
+use serde_derive::Serialize;
 use std::{
-	ffi::{CStr, CString},
 	fs,
 	io::{self, Cursor},
-	thread::sleep,
-	time::Duration,
+	path::{Path, PathBuf},
 };
-use steam::RemoteStorage;
-
 use crate::{
-	config::{self, Branch},
+	changelog,
+	check,
+	config::{self, Branch, Screenshot, Visibility},
+	guard::{self, Action},
+	inspect,
 	item::Item,
-	package,
-	project::Project,
-	util,
+	journal::{self, Journal, Run, Step},
+	package::{self, Compiled},
+	preview,
+	project::{self, Project},
+	publisher::{self, Publisher, RemoteFile},
+	retry::Retry,
 };
 
 #[derive(Debug, Display, EError, From)]
 pub enum Error {
 	#[display(fmt = "Could not generate description")]
 	DescriptionError(#[error(source)] config::DescriptionError),
+	#[display(fmt = "Could not generate change notes")]
+	Changelog(#[error(source)] changelog::Error),
+	#[display(fmt = "The branch can not be published:\n{}", _0)]
+	Invalid(check::Problems),
+	#[display(fmt = "The git repository is not ready for publishing the branch:\n{}", _0)]
+	Unguarded(check::Problems),
+	#[display(fmt = "Could not check the git repository")]
+	GuardError(#[error(source)] guard::Error),
 	#[display(fmt = "Could not read preview")]
 	Preview(#[error(source)] io::Error),
-	#[display(
-		fmt = "'.modid.{}' does not have a valid format! It should contain the Mod ID of the branch.",
-		branch
-	)]
-	InvalidModIDFileFormat { branch: String },
+	#[display(fmt = "Could not read screenshots")]
+	Screenshots(#[error(source)] config::ScreenshotError),
+	#[display(fmt = "Could not get Mod ID")]
+	ModID(#[error(source)] project::ModIDError),
 	#[display(fmt = "Could not write to '.modid.{}'", branch)]
 	WriteModIDFile { branch: String, source: io::Error },
 	#[display(fmt = "Could not write mod files to remote storage")]
-	WriteFiles(#[error(source)] steam::Error),
+	WriteFiles(#[error(source)] publisher::Error),
 	#[display(fmt = "Could not update mod")]
-	UpdateMod(#[error(source)] steam::Error),
-	#[display(fmt = "{}", _0)]
-	ReadError(#[error(defer)] util::ReadError),
+	UpdateMod(#[error(source)] publisher::Error),
+	#[display(fmt = "Could not update the screenshots and videos")]
+	UpdateGallery(#[error(source)] publisher::Error),
 	#[display(fmt = "Could not create a new mod")]
-	CreateMod(#[error(source)] steam::Error),
+	CreateMod(#[error(source)] publisher::Error),
 	#[display(fmt = "Could not package mod into a Zip archive")]
 	PackageError(#[error(source)] package::Error),
-	#[display(fmt = "Could not access Steamworks SDK interfaces")]
-	Interface,
+	#[display(fmt = "Could not write '{}'", "_0.display()")]
+	WriteDryRun(PathBuf, #[error(source)] io::Error),
+	#[display(fmt = "Could not serialize publishing summary")]
+	Summary(#[error(source)] serde_json::Error),
+	#[display(fmt = "Could not hash the package")]
+	Hash(#[error(source)] inspect::Error),
+	#[display(fmt = "Could not access the publish journal")]
+	JournalError(#[error(source)] journal::Error),
+	#[display(fmt = "There is no unfinished publish of branch '{}' to resume", _0)]
+	NothingToResume(String),
+	#[display(
+		fmt = "The package of branch '{}' changed since the unfinished publish, publish it again without resuming",
+		_0
+	)]
+	PackageChanged(String),
+	#[display(
+		fmt = "The workshop item {} belongs to the app {}, but the branch is published for the app {}",
+		item,
+		actual,
+		expected
+	)]
+	WrongApp {
+		item:     Item,
+		actual:   u32,
+		expected: u32,
+	},
 }
 
-const_cstr! {
-	PATH_ZIP = "laspad_mod.zip";
-	PATH_PREVIEW = "laspad_preview";
+/// Everything that ends up on the workshop page of a branch.
+pub struct Artefacts {
+	pub app_id:             u32,
+	pub title:              String,
+	pub tags:               Vec<String>,
+	pub visibility:         Option<Visibility>,
+	pub description:        String,
+	pub preview:            Vec<u8>,
+	pub screenshots:        Vec<Screenshot>,
+	pub videos:             Vec<String>,
+	pub zip:                Vec<u8>,
+	pub change_description: String,
 }
 
-const WRITE_ZIP_ERROR_MSG: &str = "Couldn't initiate writing ZIP file to Steam Cloud";
-const WRITE_PREVIEW_ERROR_MSG: &str = "Couldn't initiate writing preview to Steam Cloud";
-
-macro_rules! repeat {
-	($e:expr) => {
-		loop {
-			match $e {
-				Err(steam::Error::Busy) => {
-					sleep(Duration::from_millis(50));
-				},
-				v => break v,
-				}
+impl Artefacts {
+	pub fn new(
+		project: &Project,
+		compiled: &Compiled,
+		branch: &Branch,
+		branch_name: &str,
+		item: Item,
+	) -> Result<Self, Error> {
+		use self::Error::*;
+
+		let zip =
+			package::package_compiled(compiled, branch, Cursor::new(Vec::new()))?.into_inner();
+
+		let mut preview = branch.preview(project).map_err(Preview)?;
+		// mustn't be empty, so we'll make it an empty PNG
+		if preview.is_empty() {
+			if let Some(path) = &branch.preview {
+				warn!(
+					"The preview '{}' is empty, an empty image is used instead",
+					path.display()
+				);
 			}
-	};
-}
+			preview = include_bytes!("../assets/empty.png").to_vec();
+		} else if let Some(fitted) = preview::fit(&preview) {
+			warn!("The preview does not fit on the workshop, so laspad {}", fitted);
+			preview = fitted.data;
+		}
 
-fn create_workshop_item<'a>(remote: &'a steam::RemoteStorage<'a>) -> Result<Item, steam::Error> {
-	repeat!(remote
-		.file_write(PATH_ZIP.as_cstr(), [0])
-		.expect(WRITE_ZIP_ERROR_MSG)
-		.wait())?;
-
-	let name = const_cstr!("dummy").as_cstr();
-	Ok(repeat!({
-		remote
-			.publish(
-				4920,
-				PATH_ZIP.as_cstr(),
-				PATH_ZIP.as_cstr(),
-				name,
-				name,
-				&[] as &[&CStr],
-			)
-			.expect("Couldn't initiate uploading dummy mod to Steam")
-			.wait()
-	})?
-	.into())
+		let mut screenshots = branch.screenshots(project)?;
+		for screenshot in &mut screenshots {
+			if let Some(fitted) = preview::fit(&screenshot.data) {
+				warn!(
+					"The screenshot '{}' does not fit on the workshop, so laspad {}",
+					screenshot.name, fitted
+				);
+				screenshot.data = fitted.data;
+			}
+		}
+
+		Ok(Artefacts {
+			app_id: project.app_id(branch),
+			title: branch.name.clone(),
+			tags: branch.tags.clone(),
+			visibility: branch.visibility,
+			description: branch.description(project, item)?,
+			preview,
+			screenshots,
+			videos: branch.videos.clone().unwrap_or_default(),
+			zip,
+			change_description: changelog::change_note(project, branch, branch_name)?,
+		})
+	}
 }
 
-pub fn publish(project: &Project, branch: &Branch, branch_name: &str) -> Result<(), Error> {
+/// Publishes the branch, recording every step in the publish journal.
+/// With `resume`, the steps that succeeded during the last, unfinished publish of the branch are skipped.
+pub fn publish(
+	project: &Project,
+	compiled: &Compiled,
+	branch: &Branch,
+	branch_name: &str,
+	publisher: &mut dyn Publisher,
+	retry: &Retry,
+	resume: bool,
+) -> Result<Item, Error> {
 	use self::Error::*;
+	// Every branch has the whole deadline, starting with its first call to Steam
+	retry.restart();
+
+	let known = project.modid(branch, branch_name)?;
+	let mut artefacts = Artefacts::new(
+		project,
+		compiled,
+		branch,
+		branch_name,
+		known.unwrap_or(Item::PLACEHOLDER),
+	)?;
+	let problems = check::artefacts(branch_name, branch, &artefacts);
+	if !problems.is_empty() {
+		return Err(Invalid(check::Problems(problems)));
+	}
+	let mut failed = Vec::new();
+	for (action, problem) in guard::check(project, branch, branch_name)? {
+		match action {
+			Action::Fail => failed.push(problem),
+			_ => warn!("{}", problem),
+		}
+	}
+	if !failed.is_empty() {
+		return Err(Unguarded(check::Problems(failed)));
+	}
+	if !artefacts.screenshots.is_empty() || !artefacts.videos.is_empty() {
+		// Publishing without them would leave the workshop page different from the config
+		publisher.gallery().map_err(UpdateGallery)?;
+	}
+	if let Some(item) = known {
+		match publisher.app_id(item) {
+			Ok(Some(actual)) if actual != artefacts.app_id => {
+				return Err(WrongApp {
+					item,
+					actual,
+					expected: artefacts.app_id,
+				});
+			},
+			Ok(_) => (),
+			Err(e) => warn!("Could not check which app the workshop item belongs to: {}", e),
+		}
+	}
+	if artefacts.visibility == Some(Visibility::Private) && !artefacts.change_description.is_empty()
+	{
+		warn!(
+			"The branch '{}' is private, but its change notes will be public on the workshop: {}",
+			branch_name, artefacts.change_description
+		);
+	}
+
+	let hash = inspect::hash(Cursor::new(&artefacts.zip)).map_err(Hash)?;
+	let mut journal = Journal::open(project)?;
+	let mut item = known;
+	let done: Vec<Step> = if resume {
+		let runs = journal.runs(Some(branch_name));
+		let run = match runs.last() {
+			Some(run)
+				if !run
+					.iter()
+					.any(|e| e.step == Step::ItemUpdated && e.error.is_none()) =>
+			{
+				run
+			},
+			_ => return Err(NothingToResume(branch_name.into())),
+		};
+		if run[0].hash != hash {
+			return Err(PackageChanged(branch_name.into()));
+		}
+		// The item may have been created without the Mod ID being saved
+		item = item.or_else(|| run.iter().find_map(|e| e.item()));
+		run.iter()
+			.filter(|e| e.error.is_none())
+			.map(|e| e.step)
+			.collect()
+	} else {
+		Vec::new()
+	};
 
-	let mut steam = steam::STEAM.lock().expect("Couldn't lock Steam mutex");
-	let client = steam.new_client();
-	let remote = client
-		.as_ref()
-		.and_then(|c| RemoteStorage::new(c))
-		.ok_or(Error::Interface)?;
-	let item: Item = match branch.item {
+	let mut run = Run {
+		journal: &mut journal,
+		branch: branch_name.into(),
+		commit: project.head().map(|h| h.to_string()),
+		hash,
+		item,
+	};
+	// Resuming continues the last run, so that it still includes every step that succeeded
+	if !resume {
+		run.start()?;
+	}
+
+	let item: Item = match item {
 		Some(i) => i,
 		None => {
-			let path = project.path.join(format!(".modid.{}", branch_name));
-			if path.exists() {
-				util::read_to_string(path)?
-					.parse()
-					.map_err(|_| InvalidModIDFileFormat {
-						branch: branch_name.into(),
-					})?
-			} else {
-				let item = create_workshop_item(&remote).map_err(CreateMod)?;
-				info!("Created new Mod ID");
-				fs::write(path, &format!("{:X}", item.0)).map_err(|e| WriteModIDFile {
-					branch: branch_name.into(),
-					source: e,
-				})?;
-				item
-			}
+			let result = retry.run(&Step::ItemCreated.to_string(), || {
+				publisher.create_item(artefacts.app_id, branch.visibility)
+			});
+			run.item = result.as_ref().ok().cloned();
+			run.record(Step::ItemCreated, &result)?;
+			let item = result.map_err(CreateMod)?;
+			info!("Created new Mod ID {}", item);
+			item
 		},
 	};
+	if known.is_none() {
+		fs::write(project.modid_path(branch_name), format!("{:X}", item.0)).map_err(|e| {
+			WriteModIDFile {
+				branch: branch_name.into(),
+				source: e,
+			}
+		})?;
+		artefacts.description = branch.description(project, item)?;
+	}
 	info!("Mod ID: {}", item);
-	let zip = package::package(project, branch, Cursor::new(Vec::new()))?.into_inner();
 
-	// FIXME `repeat` each separately but at the same time somehow
-	repeat!({
-		let write_mod = remote
-			.file_write(PATH_ZIP.as_cstr(), &zip)
-			.expect(WRITE_ZIP_ERROR_MSG);
+	let files = [
+		(Step::ZipWritten, RemoteFile::Zip, &artefacts.zip),
+		(
+			Step::PreviewWritten,
+			RemoteFile::Preview,
+			&artefacts.preview,
+		),
+	];
+	for &(step, file, data) in &files {
+		if done.contains(&step) {
+			info!("Skipping the step to {}, it succeeded before", step);
+			continue;
+		}
+		let result = retry.run(&step.to_string(), || publisher.write_file(file, data));
+		run.record(step, &result)?;
+		result.map_err(WriteFiles)?;
+	}
 
-		let preview = branch.preview(project).map_err(Preview)?;
-		// mustn't be empty, so we'll make it an empty PNG
-		let preview = if preview.len() == 0 {
-			include_bytes!("../assets/empty.png")
-		} else {
-			&preview[..]
-		};
+	if done.contains(&Step::GalleryWritten) {
+		info!(
+			"Skipping the step to {}, it succeeded before",
+			Step::GalleryWritten
+		);
+	} else if publisher.gallery().is_ok() {
+		let result = retry.run(&Step::GalleryWritten.to_string(), || {
+			publisher.update_gallery(item, &artefacts)
+		});
+		run.record(Step::GalleryWritten, &result)?;
+		result.map_err(UpdateGallery)?;
+	}
 
-		let write_preview = remote
-			.file_write(PATH_PREVIEW.as_cstr(), preview)
-			.expect(WRITE_PREVIEW_ERROR_MSG);
-
-		write_mod.join(write_preview).wait()
-	})
-	.map_err(WriteFiles)?;
-
-	repeat!({
-		remote
-			.update(*item)
-			.title(
-				&CString::new(branch.name.as_str())
-					.expect("Couldn't generate FFI-compatible string"),
-			)
-			.unwrap()
-			.tags(
-				&branch
-					.tags
-					.iter()
-					.map(|s| {
-						CString::new(s.as_str()).expect("Couldn't generate FFI-compatible string")
-					})
-					.collect::<Vec<_>>(),
-			)
-			.unwrap()
-			.description(
-				&CString::new(branch.description(project, item)?)
-					.expect("Couldn't generate FFI-compatible string"),
-			)
-			.unwrap()
-			.preview(PATH_PREVIEW.as_cstr())
-			.expect(WRITE_PREVIEW_ERROR_MSG)
-			.file(PATH_ZIP.as_cstr())
-			.expect(WRITE_ZIP_ERROR_MSG)
-			.change_description(
-				&CString::new(
-					project
-						.head()
-						.map_or(String::new(), |id| format!("git commit: {}", id)),
-				)
-				.expect("Couldn't generate FFI-compatible string"),
-			)
-			.unwrap()
-			.finish()
-			.wait()
-	})
-	.map_err(UpdateMod)?;
+	let result = retry.run(&Step::ItemUpdated.to_string(), || {
+		publisher.update_item(item, &artefacts)
+	});
+	run.record(Step::ItemUpdated, &result)?;
+	result.map_err(UpdateMod)?;
+
+	if let Some(head) = project.head() {
+		changelog::set_published(project, branch_name, head)?;
+		if let Some(tag) = guard::tag(project, branch, branch_name, head)? {
+			info!("Tagged the published commit as '{}'", tag);
+		}
+	}
+
+	Ok(item)
+}
+
+/// Runs the whole publishing pipeline, but writes the result into `out` instead of uploading it.
+/// Steam is not needed for this, and no new workshop item is created for unpublished branches.
+pub fn dry_run(
+	project: &Project,
+	compiled: &Compiled,
+	branch: &Branch,
+	branch_name: &str,
+	out: &Path,
+) -> Result<(), Error> {
+	use self::Error::*;
+
+	#[derive(Serialize)]
+	struct File {
+		path: PathBuf,
+		size: usize,
+	}
+
+	#[derive(Serialize)]
+	struct Summary<'a> {
+		branch:             &'a str,
+		app_id:             u32,
+		item:               Option<String>,
+		url:                Option<String>,
+		title:              &'a str,
+		tags:               &'a [String],
+		visibility:         Option<Visibility>,
+		change_description: &'a str,
+		description:        File,
+		preview:            File,
+		screenshots:        Vec<File>,
+		videos:             &'a [String],
+		zip:                File,
+		problems:           Vec<String>,
+	}
+
+	let item = project.modid(branch, branch_name)?;
+	match item {
+		Some(item) => info!("Mod ID: {}", item),
+		None => info!("The branch has not been published yet, a new Mod ID would be created"),
+	}
+	let artefacts = Artefacts::new(
+		project,
+		compiled,
+		branch,
+		branch_name,
+		item.unwrap_or(Item::PLACEHOLDER),
+	)?;
+
+	let mut problems = check::artefacts(branch_name, branch, &artefacts);
+	problems.extend(
+		guard::check(project, branch, branch_name)?
+			.into_iter()
+			.map(|(_, problem)| problem),
+	);
+	for problem in &problems {
+		warn!("{}", problem);
+	}
+
+	let write = |name: &str, data: &[u8]| -> Result<File, Error> {
+		let path = out.join(name);
+		fs::write(&path, data).map_err(|e| WriteDryRun(path.clone(), e))?;
+		Ok(File {
+			path,
+			size: data.len(),
+		})
+	};
 
+	fs::create_dir_all(out).map_err(|e| WriteDryRun(out.into(), e))?;
+	let summary = Summary {
+		branch: branch_name,
+		app_id: artefacts.app_id,
+		item: item.map(|i| format!("{:X}", i)),
+		url: item.map(|i| i.url()),
+		title: &artefacts.title,
+		tags: &artefacts.tags,
+		visibility: artefacts.visibility,
+		change_description: &artefacts.change_description,
+		description: write("description.txt", artefacts.description.as_bytes())?,
+		preview: write(RemoteFile::Preview.name(), &artefacts.preview)?,
+		screenshots: artefacts
+			.screenshots
+			.iter()
+			.enumerate()
+			.map(|(i, s)| write(&format!("screenshot-{}-{}", i, s.name), &s.data))
+			.collect::<Result<_, _>>()?,
+		videos: &artefacts.videos,
+		zip: write(RemoteFile::Zip.name(), &artefacts.zip)?,
+		problems: problems.iter().map(|p| p.to_string()).collect(),
+	};
+	let summary = serde_json::to_vec_pretty(&summary).map_err(Summary)?;
+	write("summary.json", &summary)?;
+
+	info!("Wrote dry run of branch '{}' to {}", branch_name, out.display());
 	Ok(())
 }
+
+#[cfg(test)]
+#[test]
+fn test() {
+	use crate::publisher::{Call, Fake};
+
+	let root = crate::util::TempDir::new("publish");
+	fs::create_dir_all(root.join("project/src/lua")).unwrap();
+	fs::write(root.join("project/src/lua/test.lua"), "print 'hi'").unwrap();
+	fs::create_dir_all(root.join("project/screenshots")).unwrap();
+	fs::write(
+		root.join("project/screenshots/a.png"),
+		&include_bytes!("../assets/empty.png")[..],
+	)
+	.unwrap();
+	let project = Project::write(
+		root.join("project"),
+		r#"
+version = 1
+[branch.master]
+name            = "Test"
+tags            = ["Gameplay Tweak"]
+description_str = "A test"
+visibility      = "friends"
+screenshots     = ["screenshots/*.png"]
+videos          = ["dQw4w9WgXcQ"]
+"#,
+	);
+	let branch = &project.config.branches["master"];
+	let compiled = Compiled::new(&project, branch).unwrap();
+
+	let mut workshop = Fake::new(root.join("workshop")).without_gallery();
+	let result = publish(
+		&project,
+		&compiled,
+		branch,
+		"master",
+		&mut workshop,
+		&Retry::default(),
+		false,
+	);
+	match result {
+		Err(Error::UpdateGallery(publisher::Error::Unsupported(_))) => (),
+		_ => panic!("Expected the screenshots and videos to be unsupported"),
+	}
+	assert_eq!(workshop.calls, vec![]);
+	assert!(!root.join("project/.modid.master").exists());
+
+	let mut workshop = Fake::new(root.join("workshop")).busy(2);
+	let item = publish(
+		&project,
+		&compiled,
+		branch,
+		"master",
+		&mut workshop,
+		&Retry::default(),
+		false,
+	)
+	.unwrap();
+	assert_eq!(*item, steam::Item(1));
+	assert_eq!(
+		fs::read_to_string(root.join("project/.modid.master")).unwrap(),
+		"1"
+	);
+	assert_eq!(workshop.calls, vec![
+		Call::Busy,
+		Call::Busy,
+		Call::CreateItem(item, Some(Visibility::Friends)),
+		Call::Busy,
+		Call::Busy,
+		Call::WriteFile(RemoteFile::Zip),
+		Call::Busy,
+		Call::Busy,
+		Call::WriteFile(RemoteFile::Preview),
+		Call::Busy,
+		Call::Busy,
+		Call::UpdateGallery(item),
+		Call::Busy,
+		Call::Busy,
+		Call::UpdateItem(item),
+	]);
+	let metadata = fs::read_to_string(workshop.item_path(item).join("item.json")).unwrap();
+	assert!(metadata.contains("[b]Mod ID: 1[/b]"));
+	assert!(metadata.contains("A test"));
+	assert_eq!(workshop.app_id(item).unwrap(), Some(config::NS2_APP_ID));
+	assert!(workshop
+		.item_path(item)
+		.join("screenshots/0-a.png")
+		.exists());
+	assert_eq!(
+		fs::read_to_string(workshop.item_path(item).join("videos.txt")).unwrap(),
+		"dQw4w9WgXcQ"
+	);
+
+	let resumed = publish(
+		&project,
+		&compiled,
+		branch,
+		"master",
+		&mut workshop,
+		&Retry::default(),
+		true,
+	);
+	match resumed {
+		Err(Error::NothingToResume(_)) => (),
+		_ => panic!("Expected nothing to resume"),
+	}
+
+	// Pretend the last publish stopped after writing the zip
+	let mut journal = Journal::open(&project).unwrap();
+	let mut run = Run {
+		hash:    journal.entries[0].hash.clone(),
+		journal: &mut journal,
+		branch:  "master".into(),
+		commit:  None,
+		item:    Some(item),
+	};
+	run.start().unwrap();
+	run.record(Step::ZipWritten, &Ok::<(), Error>(())).unwrap();
+	let mut workshop = Fake::new(root.join("workshop"));
+	assert_eq!(
+		publish(
+			&project,
+			&compiled,
+			branch,
+			"master",
+			&mut workshop,
+			&Retry::default(),
+			true
+		)
+		.unwrap(),
+		item
+	);
+	assert_eq!(
+		workshop.calls,
+		vec![
+			Call::WriteFile(RemoteFile::Preview),
+			Call::UpdateGallery(item),
+			Call::UpdateItem(item),
+		]
+	);
+	let journal = Journal::open(&project).unwrap();
+	assert_eq!(journal.runs(Some("master")).len(), 2);
+
+	let mut workshop = Fake::new(root.join("workshop"));
+	assert_eq!(
+		publish(
+			&project,
+			&compiled,
+			branch,
+			"master",
+			&mut workshop,
+			&Retry::default(),
+			false
+		)
+		.unwrap(),
+		item
+	);
+	assert_eq!(workshop.calls, vec![
+		Call::WriteFile(RemoteFile::Zip),
+		Call::WriteFile(RemoteFile::Preview),
+		Call::UpdateGallery(item),
+		Call::UpdateItem(item),
+	]);
+
+	// What laspad writes itself doesn't make the tree dirty for the next publish
+	let project = Project::write(
+		root.join("guarded"),
+		"version = 1\n[branch.master]\nname = \"Test\"\ntags = []\ndescription_str = \"A test\"\n\
+		 [branch.master.guard]\ndirty = \"fail\"\nunpushed = \"ignore\"\ntag = true\n",
+	);
+	fs::create_dir_all(root.join("guarded/src/lua")).unwrap();
+	fs::write(root.join("guarded/src/lua/test.lua"), "print 'hi'").unwrap();
+	let repo = git2::Repository::init(&project.path).unwrap();
+	let mut index = repo.index().unwrap();
+	index
+		.add_all(["laspad.toml", "src"].iter(), Default::default(), None)
+		.unwrap();
+	let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+	let signature = git2::Signature::now("test", "test@example.com").unwrap();
+	repo.commit(Some("HEAD"), &signature, &signature, "init", &tree, &[]).unwrap();
+	fs::write(project.path.join(crate::lock::Lock::PATH), "").unwrap();
+	let branch = &project.config.branches["master"];
+	let compiled = Compiled::new(&project, branch).unwrap();
+	let mut workshop = Fake::new(root.join("workshop"));
+	for _ in 0..2 {
+		publish(
+			&project,
+			&compiled,
+			branch,
+			"master",
+			&mut workshop,
+			&Retry::default(),
+			false,
+		)
+		.unwrap();
+	}
+	assert!(repo.find_reference("refs/tags/workshop/master/2").is_ok());
+}
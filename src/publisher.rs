@@ -0,0 +1,419 @@
+use const_cstr::const_cstr;
+use derive_more::Display;
+use erroneous::Error as EError;
+use futures::Future;
+use serde_derive::Serialize;
+use std::{
+	env,
+	ffi::{CStr, CString},
+	fs,
+	io,
+	path::{Path, PathBuf},
+};
+use steam::RemoteStorage;
+
+use crate::{config::Visibility, download, item::Item, publish::Artefacts, steamcmd};
+
+#[derive(Debug, Display, EError)]
+pub enum Error {
+	#[display(fmt = "The workshop is busy")]
+	Busy,
+	#[display(
+		fmt = "The workshop was still busy after {} seconds and {} attempts",
+		_0,
+		_1
+	)]
+	Timeout(u64, u32),
+	#[display(fmt = "Steam returned an error")]
+	Steam(#[error(source)] steam::Error),
+	#[display(fmt = "Steam does not accept the visibility {:?}", _0)]
+	Visibility(Visibility),
+	#[display(fmt = "Could not access Steamworks SDK interfaces")]
+	Interface,
+	#[display(
+		fmt = "'{}' is for the app {} instead of {}, remove it or run laspad elsewhere",
+		"_0.display()",
+		_1,
+		_2
+	)]
+	StaleAppIdFile(PathBuf, String, u32),
+	#[display(fmt = "Could not access '{}' of the fake workshop", "_0.display()")]
+	Fake(PathBuf, #[error(source)] io::Error),
+	#[display(fmt = "Could not publish through steamcmd")]
+	SteamCmd(#[error(source)] steamcmd::Error),
+	#[display(fmt = "Could not get the details of the workshop item")]
+	Details(#[error(source)] download::Error),
+	#[display(fmt = "{} can not upload screenshots and videos", _0)]
+	Unsupported(&'static str),
+}
+
+impl From<steam::Error> for Error {
+	fn from(e: steam::Error) -> Self {
+		match e {
+			steam::Error::Busy => Error::Busy,
+			e => Error::Steam(e),
+		}
+	}
+}
+
+impl From<steamcmd::Error> for Error {
+	fn from(e: steamcmd::Error) -> Self {
+		Error::SteamCmd(e)
+	}
+}
+
+/// The operations needed to publish a mod to the workshop.
+/// Any of them may fail with `Error::Busy`, in which case they should be retried.
+pub trait Publisher {
+	/// Creates a new empty workshop item of the app, with the default visibility if `None`.
+	/// Backends that can't set the visibility right away leave it to `update_item`.
+	fn create_item(&mut self, app_id: u32, visibility: Option<Visibility>) -> Result<Item, Error>;
+	/// Uploads a file which the next `update_item` will attach to the item.
+	fn write_file(&mut self, file: RemoteFile, data: &[u8]) -> Result<(), Error>;
+	/// Updates the metadata of the item and replaces its files with the ones last written.
+	fn update_item(&mut self, item: Item, artefacts: &Artefacts) -> Result<(), Error>;
+	/// Fails with `Error::Unsupported` if the backend can't update screenshots and videos.
+	fn gallery(&self) -> Result<(), Error> {
+		Ok(())
+	}
+	/// Replaces the screenshots and videos of the item, removing the ones no longer listed.
+	fn update_gallery(&mut self, item: Item, artefacts: &Artefacts) -> Result<(), Error>;
+	/// The app ID the item belongs to, `None` if it can't be found out.
+	fn app_id(&mut self, item: Item) -> Result<Option<u32>, Error> {
+		Ok(download::get_info(item)
+			.map_err(Error::Details)?
+			.consumer_app_id)
+	}
+}
+
+const_cstr! {
+	PATH_ZIP = "laspad_mod.zip";
+	PATH_PREVIEW = "laspad_preview";
+}
+
+/// The files that make up a workshop item
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RemoteFile {
+	Zip,
+	Preview,
+}
+
+impl RemoteFile {
+	pub fn path(self) -> &'static CStr {
+		match self {
+			RemoteFile::Zip => PATH_ZIP.as_cstr(),
+			RemoteFile::Preview => PATH_PREVIEW.as_cstr(),
+		}
+	}
+
+	pub fn name(self) -> &'static str {
+		match self {
+			RemoteFile::Zip => PATH_ZIP.to_str(),
+			RemoteFile::Preview => PATH_PREVIEW.to_str(),
+		}
+	}
+}
+
+const WRITE_ZIP_ERROR_MSG: &str = "Couldn't initiate writing ZIP file to Steam Cloud";
+const WRITE_PREVIEW_ERROR_MSG: &str = "Couldn't initiate writing preview to Steam Cloud";
+
+fn cstring(s: &str) -> CString {
+	CString::new(s).expect("Couldn't generate FFI-compatible string")
+}
+
+/// Publishes through the Steamworks SDK, which requires a running Steam client.
+pub struct Steam<'a> {
+	remote: RemoteStorage<'a>,
+}
+
+impl<'a> Steam<'a> {
+	/// Runs `f` with the Steam client, as the game with the app ID.
+	/// The app ID is passed to the SDK through `SteamAppId`, but a `steam_appid.txt` in the
+	/// working directory, like the one shipped with laspad for Natural Selection 2, could still
+	/// take precedence, so one for another app is refused.
+	/// Since the SDK is only initialised once, the app ID can't change later in the process.
+	pub fn with<T>(app_id: u32, f: impl FnOnce(&mut Steam) -> T) -> Result<T, Error> {
+		let file = Path::new("steam_appid.txt");
+		if let Ok(found) = fs::read_to_string(file) {
+			let found = found.trim();
+			if found.parse::<u32>().ok() != Some(app_id) {
+				let path = env::current_dir().map_or(file.into(), |d| d.join(file));
+				return Err(Error::StaleAppIdFile(path, found.into(), app_id));
+			}
+		}
+		env::set_var("SteamAppId", app_id.to_string());
+		env::set_var("SteamGameId", app_id.to_string());
+		let mut steam = steam::STEAM.lock().expect("Couldn't lock Steam mutex");
+		let client = steam.new_client();
+		let remote = client
+			.as_ref()
+			.and_then(RemoteStorage::new)
+			.ok_or(Error::Interface)?;
+		Ok(f(&mut Steam { remote }))
+	}
+}
+
+impl<'a> Publisher for Steam<'a> {
+	fn create_item(&mut self, app_id: u32, _: Option<Visibility>) -> Result<Item, Error> {
+		self.remote
+			.file_write(PATH_ZIP.as_cstr(), [0])
+			.expect(WRITE_ZIP_ERROR_MSG)
+			.wait()?;
+
+		let name = const_cstr!("dummy").as_cstr();
+		let item = self
+			.remote
+			.publish(
+				app_id,
+				PATH_ZIP.as_cstr(),
+				PATH_ZIP.as_cstr(),
+				name,
+				name,
+				&[] as &[&CStr],
+			)
+			.expect("Couldn't initiate uploading dummy mod to Steam")
+			.wait()?;
+
+		// `publish` can't set the visibility, `update_item` does.
+		// Setting it here would make a retry after a busy workshop create another item.
+		Ok(item.into())
+	}
+
+	fn write_file(&mut self, file: RemoteFile, data: &[u8]) -> Result<(), Error> {
+		self.remote
+			.file_write(file.path(), data)
+			.expect(match file {
+				RemoteFile::Zip => WRITE_ZIP_ERROR_MSG,
+				RemoteFile::Preview => WRITE_PREVIEW_ERROR_MSG,
+			})
+			.wait()?;
+		Ok(())
+	}
+
+	fn update_item(&mut self, item: Item, artefacts: &Artefacts) -> Result<(), Error> {
+		let update = self
+			.remote
+			.update(*item)
+			.title(&cstring(&artefacts.title))
+			.unwrap()
+			.tags(
+				&artefacts
+					.tags
+					.iter()
+					.map(|s| cstring(s))
+					.collect::<Vec<_>>(),
+			)
+			.unwrap()
+			.description(&cstring(&artefacts.description))
+			.unwrap()
+			.preview(PATH_PREVIEW.as_cstr())
+			.expect(WRITE_PREVIEW_ERROR_MSG)
+			.file(PATH_ZIP.as_cstr())
+			.expect(WRITE_ZIP_ERROR_MSG)
+			.change_description(&cstring(&artefacts.change_description))
+			.unwrap();
+		let update = match artefacts.visibility {
+			Some(visibility) => update
+				.visibility(visibility.as_steam())
+				.map_err(|_| Error::Visibility(visibility))?,
+			None => update,
+		};
+		update.finish().wait()?;
+		Ok(())
+	}
+
+	fn gallery(&self) -> Result<(), Error> {
+		// `ISteamRemoteStorage` only knows about the main preview
+		Err(Error::Unsupported("The Steam client"))
+	}
+
+	fn update_gallery(&mut self, _: Item, _: &Artefacts) -> Result<(), Error> {
+		self.gallery()
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Call {
+	CreateItem(Item, Option<Visibility>),
+	WriteFile(RemoteFile),
+	UpdateItem(Item),
+	UpdateGallery(Item),
+	Busy,
+}
+
+/// A workshop on the local filesystem, useful for testing without Steam.
+/// Every item is a directory in `root` named after its ID, containing the uploaded files
+/// and its metadata in `item.json`.
+pub struct Fake {
+	pub root:  PathBuf,
+	/// Every call made so far, including the ones that failed with `Error::Busy`.
+	pub calls: Vec<Call>,
+	busy:      u32,
+	busy_left: u32,
+	gallery:   bool,
+}
+
+impl Fake {
+	pub fn new(root: impl Into<PathBuf>) -> Self {
+		Fake {
+			root:      root.into(),
+			calls:     Vec::new(),
+			busy:      0,
+			busy_left: 0,
+			gallery:   true,
+		}
+	}
+
+	/// Makes each call fail `n` times with `Error::Busy` before it succeeds.
+	#[cfg(test)]
+	pub fn busy(self, n: u32) -> Self {
+		Fake {
+			busy: n,
+			busy_left: n,
+			..self
+		}
+	}
+
+	/// Makes it unable to update screenshots and videos, like the Steam client.
+	#[cfg(test)]
+	pub fn without_gallery(self) -> Self {
+		Fake {
+			gallery: false,
+			..self
+		}
+	}
+
+	fn check_busy(&mut self) -> Result<(), Error> {
+		if self.busy_left > 0 {
+			self.busy_left -= 1;
+			self.calls.push(Call::Busy);
+			Err(Error::Busy)
+		} else {
+			self.busy_left = self.busy;
+			Ok(())
+		}
+	}
+
+	fn app_id_path(&self, item: Item) -> PathBuf {
+		self.item_path(item).join("app_id")
+	}
+
+	fn staging(&self) -> PathBuf {
+		self.root.join(".staging")
+	}
+
+	pub fn item_path(&self, item: Item) -> PathBuf {
+		self.root.join(format!("{:X}", item))
+	}
+}
+
+fn fake_io<T>(path: &Path, r: io::Result<T>) -> Result<T, Error> {
+	r.map_err(|e| Error::Fake(path.into(), e))
+}
+
+impl Publisher for Fake {
+	fn create_item(&mut self, app_id: u32, visibility: Option<Visibility>) -> Result<Item, Error> {
+		self.check_busy()?;
+		fake_io(&self.root, fs::create_dir_all(&self.root))?;
+		let mut last = 0;
+		for entry in fake_io(&self.root, fs::read_dir(&self.root))? {
+			let entry = fake_io(&self.root, entry)?;
+			if let Some(id) = entry
+				.file_name()
+				.to_str()
+				.and_then(|s| u64::from_str_radix(s, 16).ok())
+			{
+				last = last.max(id);
+			}
+		}
+		let item: Item = steam::Item(last + 1).into();
+		let path = self.item_path(item);
+		fake_io(&path, fs::create_dir(&path))?;
+		let app_id_path = self.app_id_path(item);
+		fake_io(&app_id_path, fs::write(&app_id_path, app_id.to_string()))?;
+		info!("Created fake workshop item {}", path.display());
+		self.calls.push(Call::CreateItem(item, visibility));
+		Ok(item)
+	}
+
+	fn write_file(&mut self, file: RemoteFile, data: &[u8]) -> Result<(), Error> {
+		self.check_busy()?;
+		let staging = self.staging();
+		fake_io(&staging, fs::create_dir_all(&staging))?;
+		let path = staging.join(file.name());
+		fake_io(&path, fs::write(&path, data))?;
+		self.calls.push(Call::WriteFile(file));
+		Ok(())
+	}
+
+	fn update_item(&mut self, item: Item, artefacts: &Artefacts) -> Result<(), Error> {
+		#[derive(Serialize)]
+		struct Metadata<'a> {
+			title:              &'a str,
+			tags:               &'a [String],
+			visibility:         Option<Visibility>,
+			description:        &'a str,
+			change_description: &'a str,
+		}
+
+		self.check_busy()?;
+		let path = self.item_path(item);
+		if !path.is_dir() {
+			return Err(Error::Fake(
+				path,
+				io::Error::new(io::ErrorKind::NotFound, "No such workshop item"),
+			));
+		}
+		for file in &[RemoteFile::Zip, RemoteFile::Preview] {
+			let src = self.staging().join(file.name());
+			fake_io(&src, fs::copy(&src, path.join(file.name())))?;
+		}
+		let metadata = serde_json::to_vec_pretty(&Metadata {
+			title:              &artefacts.title,
+			tags:               &artefacts.tags,
+			visibility:         artefacts.visibility,
+			description:        &artefacts.description,
+			change_description: &artefacts.change_description,
+		})
+		.expect("Could not serialize metadata");
+		let metadata_path = path.join("item.json");
+		fake_io(&metadata_path, fs::write(&metadata_path, metadata))?;
+		self.calls.push(Call::UpdateItem(item));
+		Ok(())
+	}
+
+	fn gallery(&self) -> Result<(), Error> {
+		if self.gallery {
+			Ok(())
+		} else {
+			Err(Error::Unsupported("The fake workshop"))
+		}
+	}
+
+	fn update_gallery(&mut self, item: Item, artefacts: &Artefacts) -> Result<(), Error> {
+		self.gallery()?;
+		self.check_busy()?;
+		let path = self.item_path(item).join("screenshots");
+		if path.exists() {
+			fake_io(&path, fs::remove_dir_all(&path))?;
+		}
+		fake_io(&path, fs::create_dir_all(&path))?;
+		for (i, screenshot) in artefacts.screenshots.iter().enumerate() {
+			let path = path.join(format!("{}-{}", i, screenshot.name));
+			fake_io(&path, fs::write(&path, &screenshot.data))?;
+		}
+		let videos = self.item_path(item).join("videos.txt");
+		fake_io(&videos, fs::write(&videos, artefacts.videos.join("\n")))?;
+		self.calls.push(Call::UpdateGallery(item));
+		Ok(())
+	}
+
+	fn app_id(&mut self, item: Item) -> Result<Option<u32>, Error> {
+		let path = self.app_id_path(item);
+		if !path.exists() {
+			return Ok(None);
+		}
+		let app_id = fake_io(&path, fs::read_to_string(&path))?;
+		Ok(app_id.trim().parse().ok())
+	}
+}
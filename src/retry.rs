@@ -0,0 +1,137 @@
+use std::{
+	cell::Cell,
+	cmp::min,
+	thread::sleep,
+	time::{Duration, Instant},
+};
+
+use crate::publisher::Error;
+
+/// How calls that fail with `Error::Busy` are retried.
+/// The deadline is shared by the calls of a branch, so its publish gives up after it at the latest.
+pub struct Retry {
+	/// When the first call since the last `restart` started
+	start:            Cell<Option<Instant>>,
+	/// How long after the first call to keep retrying before giving up
+	pub deadline:     Duration,
+	/// How long to wait after the first failure, doubled after every further one
+	pub backoff:      Duration,
+	/// The longest to wait between two attempts
+	pub max_backoff:  Duration,
+	/// How often to report that we are still waiting
+	pub log_interval: Duration,
+}
+
+impl Default for Retry {
+	fn default() -> Self {
+		Retry {
+			start:        Cell::new(None),
+			deadline:     Duration::from_secs(10 * 60),
+			backoff:      Duration::from_millis(50),
+			max_backoff:  Duration::from_secs(5),
+			log_interval: Duration::from_secs(10),
+		}
+	}
+}
+
+impl Retry {
+	/// Starts the deadline again with the next call, e.g. for the next branch.
+	pub fn restart(&self) {
+		self.start.set(None);
+	}
+
+	/// Calls `f` until it doesn't fail with `Error::Busy` or the deadline has passed.
+	/// `what` describes what is being waited on for the log.
+	pub fn run<T>(&self, what: &str, mut f: impl FnMut() -> Result<T, Error>) -> Result<T, Error> {
+		let start = Instant::now();
+		let first = self.start.get().unwrap_or(start);
+		self.start.set(Some(first));
+		let mut last_log = start;
+		let mut backoff = self.backoff;
+		let mut attempts = 1;
+		loop {
+			match f() {
+				Err(Error::Busy) => (),
+				v => return v,
+			}
+
+			let elapsed = start.elapsed();
+			let total = first.elapsed();
+			if total >= self.deadline {
+				return Err(Error::Timeout(total.as_secs(), attempts));
+			}
+			if last_log.elapsed() >= self.log_interval {
+				info!(
+					"Steam is busy, still waiting to {} after {} seconds and {} attempts",
+					what,
+					elapsed.as_secs(),
+					attempts
+				);
+				last_log = Instant::now();
+			} else {
+				debug!("Steam is busy, retrying to {} in {:?}", what, backoff);
+			}
+
+			sleep(min(backoff, self.deadline - total));
+			backoff = min(backoff * 2, self.max_backoff);
+			attempts += 1;
+		}
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+	let retry = Retry {
+		start:        Cell::new(None),
+		deadline:     Duration::from_millis(100),
+		backoff:      Duration::from_millis(1),
+		max_backoff:  Duration::from_millis(10),
+		log_interval: Duration::from_secs(1),
+	};
+
+	let mut calls = 0;
+	let result = retry.run("test", || {
+		calls += 1;
+		if calls < 3 {
+			Err(Error::Busy)
+		} else {
+			Ok(calls)
+		}
+	});
+	assert_eq!(result.unwrap(), 3);
+
+	let start = Instant::now();
+	match retry.run("test", || -> Result<(), Error> { Err(Error::Busy) }) {
+		Err(Error::Timeout(_, attempts)) => assert!(attempts > 1),
+		_ => panic!("Expected a timeout"),
+	}
+	let first = retry.start.get().unwrap();
+	assert!(first.elapsed() >= retry.deadline);
+	assert!(start.elapsed() < retry.deadline + retry.max_backoff * 5);
+
+	// The deadline has passed, so busy calls are not retried anymore
+	let mut calls = 0;
+	let result = retry.run("test", || {
+		calls += 1;
+		Err::<(), _>(Error::Busy)
+	});
+	match result {
+		Err(Error::Timeout(_, 1)) => assert_eq!(calls, 1),
+		_ => panic!("Expected a timeout after one attempt"),
+	}
+
+	// The next branch gets its own deadline, which starts with its first call
+	retry.restart();
+	sleep(retry.deadline);
+	let mut calls = 0;
+	let result = retry.run("test", || {
+		calls += 1;
+		if calls < 2 {
+			Err(Error::Busy)
+		} else {
+			Ok(calls)
+		}
+	});
+	assert_eq!(result.unwrap(), 2);
+}
@@ -0,0 +1,286 @@
+/// What a line of a TOML file holds, as far as editing it line by line is concerned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+	/// Only whitespace or a comment
+	Blank,
+	/// A table header
+	Header,
+	/// The start of a key/value pair
+	Key,
+	/// More of a value that started on an earlier line
+	Value,
+}
+
+/// A line of a TOML file, as found by `lines`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+	pub kind:   Kind,
+	/// The keys of the table of a header, of the key including its table, or else of the table
+	/// the line is in, without their quotes
+	pub path:   Vec<String>,
+	/// The byte index where the header or key starts
+	pub indent: usize,
+	/// Whether a value is still open at the end of the line and continues on the next one
+	pub open:   bool,
+}
+
+impl Line {
+	/// Whether the line is a header or key with this path.
+	pub fn is(&self, kind: Kind, path: &[&str]) -> bool {
+		self.kind == kind
+			&& self.path.len() == path.len()
+			&& self.path.iter().zip(path).all(|(a, b)| a == b)
+	}
+}
+
+/// The keys of a dotted key or table header, without their quotes.
+fn key_path(s: &str) -> Vec<String> {
+	let mut keys = Vec::new();
+	let mut key = String::new();
+	let mut quote = None;
+	for c in s.chars() {
+		match (quote, c) {
+			(None, '"') | (None, '\'') => quote = Some(c),
+			(Some(q), c) if c == q => quote = None,
+			(None, '.') => keys.push(std::mem::take(&mut key)),
+			(None, c) if c.is_whitespace() => (),
+			(_, c) => key.push(c),
+		}
+	}
+	keys.push(key);
+	keys
+}
+
+/// The byte index of the `=` of a key/value pair.
+fn key_end(line: &str) -> Option<usize> {
+	let mut quote = None;
+	for (i, c) in line.char_indices() {
+		match (quote, c) {
+			(None, '"') | (None, '\'') => quote = Some(c),
+			(Some(q), c) if c == q => quote = None,
+			(None, '=') => return Some(i),
+			(None, '#') => return None,
+			_ => (),
+		}
+	}
+	None
+}
+
+/// Follows the strings and brackets of (part of) a value. Returns the depth of the brackets
+/// after it and the delimiter of a multi-line string it leaves open.
+fn follow(value: &str, mut depth: i32) -> (i32, Option<&'static str>) {
+	let mut skip = 0;
+	for (i, c) in value.char_indices() {
+		if i < skip {
+			continue;
+		}
+		match c {
+			'#' => break,
+			'[' | '{' => depth += 1,
+			']' | '}' => depth -= 1,
+			'"' | '\'' => {
+				let delimiter = if c == '"' { "\"\"\"" } else { "'''" };
+				if value[i..].starts_with(delimiter) {
+					match value[i + 3..].find(delimiter) {
+						Some(end) => skip = i + 3 + end + 3,
+						None => return (depth, Some(delimiter)),
+					}
+				} else {
+					// Only basic strings have escapes
+					let mut escaped = false;
+					let end = value[i + 1..].char_indices().find(|&(_, d)| {
+						let end = d == c && !escaped;
+						escaped = c == '"' && d == '\\' && !escaped;
+						end
+					});
+					match end {
+						Some((end, _)) => skip = i + 1 + end + 1,
+						None => break,
+					}
+				}
+			},
+			_ => (),
+		}
+	}
+	(depth, None)
+}
+
+/// Finds the tables, keys and multi-line values of the lines of a TOML file,
+/// without parsing the values themselves.
+pub fn lines<S: AsRef<str>>(lines: &[S]) -> Vec<Line> {
+	let mut scanned = Vec::with_capacity(lines.len());
+	let mut table = Vec::new();
+	let mut depth = 0;
+	let mut multiline = None;
+	for line in lines {
+		let line = line.as_ref();
+		let mut current = Line {
+			kind:   if depth == 0 { Kind::Blank } else { Kind::Value },
+			path:   table.clone(),
+			indent: line.len() - line.trim_start().len(),
+			open:   false,
+		};
+
+		let mut value = line;
+		let mut statement = depth == 0;
+		if let Some(delimiter) = multiline {
+			current.kind = Kind::Value;
+			match line.find(delimiter) {
+				Some(end) => {
+					multiline = None;
+					value = &line[end + 3..];
+					statement = false;
+				},
+				None => {
+					current.open = true;
+					scanned.push(current);
+					continue;
+				},
+			}
+		}
+
+		if statement {
+			let trimmed = line.trim_start();
+			if trimmed.starts_with('[') {
+				let header = trimmed.trim_start_matches('[');
+				let end = header.find(']').unwrap_or(header.len());
+				table = key_path(&header[..end]);
+				current.kind = Kind::Header;
+				current.path = table.clone();
+				scanned.push(current);
+				continue;
+			}
+			if let Some(end) = key_end(trimmed) {
+				current.kind = Kind::Key;
+				current.path.extend(key_path(&trimmed[..end]));
+				value = &trimmed[end + 1..];
+			}
+		}
+
+		let (d, m) = follow(value, depth);
+		depth = d;
+		multiline = m;
+		current.open = depth > 0 || multiline.is_some();
+		scanned.push(current);
+	}
+	scanned
+}
+
+/// The byte index where the code of a line ends and its comment, if any, begins.
+pub fn code_end(line: &str) -> usize {
+	let mut quote = None;
+	let mut escaped = false;
+	for (i, c) in line.char_indices() {
+		match (quote, c) {
+			(Some('"'), '\\') if !escaped => {
+				escaped = true;
+				continue;
+			},
+			(Some(q), c) if c == q && !escaped => quote = None,
+			(None, '"') | (None, '\'') => quote = Some(c),
+			(None, '#') => return i,
+			_ => (),
+		}
+		escaped = false;
+	}
+	line.len()
+}
+
+/// The byte ranges of the basic strings in the code of a line, without their quotes,
+/// leaving out those in inline tables.
+pub fn strings(line: &str) -> Vec<(usize, usize)> {
+	let code = &line[..code_end(line)];
+	let mut strings = Vec::new();
+	let mut start = None;
+	let mut escaped = false;
+	let mut depth = 0;
+	for (i, c) in code.char_indices() {
+		match (start, c) {
+			(Some(_), '\\') if !escaped => {
+				escaped = true;
+				continue;
+			},
+			(Some(s), '"') if !escaped => {
+				if depth == 0 {
+					strings.push((s, i));
+				}
+				start = None;
+			},
+			(None, '"') => start = Some(i + 1),
+			(None, '{') => depth += 1,
+			(None, '}') => depth -= 1,
+			_ => (),
+		}
+		escaped = false;
+	}
+	strings
+}
+
+/// The line ending of `text`, so that edits can keep it.
+pub fn line_ending(text: &str) -> &'static str {
+	if text.contains("\r\n") {
+		"\r\n"
+	} else {
+		"\n"
+	}
+}
+
+/// Joins the lines of `text`, split by `str::lines` and edited, with its line ending again.
+pub fn join(lines: &[String], text: &str) -> String {
+	let ending = line_ending(text);
+	let mut joined = lines.join(ending);
+	joined.push_str(ending);
+	joined
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+	let text = r#"version = 2 # the version
+dependencies = [
+	"1A", # Some mod
+	{ path = "lib" },
+]
+description = """
+[branch.fake]
+"""
+
+  [branch."a.b"]   # a comment
+name = "Test # not a comment"
+'quoted key'.sub = 'x'
+"#;
+	let split: Vec<&str> = text.lines().collect();
+	let scanned: Vec<_> = lines(&split)
+		.into_iter()
+		.map(|l| (l.kind, l.path.join("/"), l.indent, l.open))
+		.collect();
+	let line = |kind, path: &str, indent, open| (kind, path.to_owned(), indent, open);
+	assert_eq!(scanned, vec![
+		line(Kind::Key, "version", 0, false),
+		line(Kind::Key, "dependencies", 0, true),
+		line(Kind::Value, "", 1, true),
+		line(Kind::Value, "", 1, true),
+		line(Kind::Value, "", 0, false),
+		line(Kind::Key, "description", 0, true),
+		line(Kind::Value, "", 0, true),
+		line(Kind::Value, "", 0, false),
+		line(Kind::Blank, "", 0, false),
+		line(Kind::Header, "branch/a.b", 2, false),
+		line(Kind::Key, "branch/a.b/name", 0, false),
+		line(Kind::Key, "branch/a.b/quoted key/sub", 0, false),
+	]);
+	assert!(lines(&split)[9].is(Kind::Header, &["branch", "a.b"]));
+	assert!(!lines(&split)[9].is(Kind::Key, &["branch", "a.b"]));
+
+	assert_eq!(code_end(split[0]), 12);
+	assert_eq!(code_end(split[10]), split[10].len());
+	assert_eq!(strings(split[2]), vec![(2, 4)]);
+	assert_eq!(strings(split[3]), vec![]);
+	assert_eq!(strings(r#""a\"b", "c" # "d""#), vec![(1, 5), (9, 10)]);
+
+	let crlf = text.replace('\n', "\r\n");
+	let split: Vec<String> = crlf.lines().map(String::from).collect();
+	assert_eq!(lines(&split)[9].path, vec!["branch", "a.b"]);
+	assert_eq!(join(&split, &crlf), crlf);
+	assert_eq!(join(&split, text), text);
+}
@@ -0,0 +1,234 @@
+use derive_more::{Display, From};
+use erroneous::Error as EError;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{
+	fmt,
+	fs,
+	io::{self, Cursor},
+	path::{Path, PathBuf},
+	process::{Command, ExitStatus},
+};
+
+use crate::{
+	config::Visibility,
+	download,
+	item::Item,
+	publish::Artefacts,
+	publisher::{self, Publisher, RemoteFile},
+};
+
+#[derive(Debug, Display, EError, From)]
+pub enum Error {
+	#[display(fmt = "Could not write '{}'", "_0.display()")]
+	Write(PathBuf, #[error(source)] io::Error),
+	#[display(fmt = "Could not extract the mod into the content folder")]
+	Extract(#[error(source)] download::Error),
+	#[display(fmt = "Could not run '{}'", "_0.display()")]
+	Run(PathBuf, #[error(source)] io::Error),
+	#[display(fmt = "steamcmd failed: {}", _0)]
+	Failed(ExitStatus),
+	#[display(fmt = "Could not find the ID of the new workshop item in '{}'", "_0.display()")]
+	NoItem(PathBuf),
+}
+
+/// A `workshop_build_item` script for steamcmd.
+pub struct Vdf<'a> {
+	pub appid:           u32,
+	pub publishedfileid: u64,
+	pub contentfolder:   &'a Path,
+	pub previewfile:     &'a Path,
+	pub visibility:      Option<u32>,
+	pub title:           &'a str,
+	pub description:     &'a str,
+	pub tags:            &'a [String],
+	pub changenote:      &'a str,
+}
+
+fn escape(s: &str) -> String {
+	s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl<'a> fmt::Display for Vdf<'a> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let field = |f: &mut fmt::Formatter, key: &str, value: &dyn fmt::Display| {
+			writeln!(f, "\t\"{}\"\t\"{}\"", key, escape(&value.to_string()))
+		};
+
+		writeln!(f, "\"workshopitem\"\n{{")?;
+		field(f, "appid", &self.appid)?;
+		field(f, "publishedfileid", &self.publishedfileid)?;
+		field(f, "contentfolder", &self.contentfolder.display())?;
+		field(f, "previewfile", &self.previewfile.display())?;
+		if let Some(visibility) = self.visibility {
+			field(f, "visibility", &visibility)?;
+		}
+		field(f, "title", &self.title)?;
+		field(f, "description", &self.description)?;
+		field(f, "tags", &self.tags.join(","))?;
+		field(f, "changenote", &self.changenote)?;
+		writeln!(f, "}}")
+	}
+}
+
+/// Publishes through steamcmd's `workshop_build_item`, which needs neither the Steam client
+/// nor the Steamworks SDK. The content folder, preview and script are kept in `dir`.
+pub struct SteamCmd {
+	pub binary:   PathBuf,
+	pub username: String,
+	pub dir:      PathBuf,
+}
+
+impl SteamCmd {
+	fn content(&self) -> PathBuf {
+		self.dir.join("content")
+	}
+
+	fn preview(&self) -> PathBuf {
+		self.dir.join("preview")
+	}
+
+	fn script(&self) -> PathBuf {
+		self.dir.join("workshop_build_item.vdf")
+	}
+
+	/// Runs the script and returns the ID of the item as written back into it by steamcmd.
+	fn run(&self, vdf: &Vdf) -> Result<u64, Error> {
+		lazy_static! {
+			static ref ID_RE: Regex =
+				Regex::new(r#""publishedfileid"\s+"([0-9]+)""#).expect("Could not generate regex");
+		}
+
+		let script = self.script();
+		fs::write(&script, vdf.to_string()).map_err(|e| Error::Write(script.clone(), e))?;
+
+		info!("Running {} workshop_build_item", self.binary.display());
+		let status = Command::new(&self.binary)
+			.arg("+login")
+			.arg(&self.username)
+			.arg("+workshop_build_item")
+			.arg(&script)
+			.arg("+quit")
+			.status()
+			.map_err(|e| Error::Run(self.binary.clone(), e))?;
+		if !status.success() {
+			return Err(Error::Failed(status));
+		}
+
+		let script_content = fs::read_to_string(&script).unwrap_or_default();
+		ID_RE
+			.captures(&script_content)
+			.and_then(|c| c[1].parse().ok())
+			.filter(|&id| id != 0)
+			.ok_or(Error::NoItem(script))
+	}
+}
+
+impl Publisher for SteamCmd {
+	fn create_item(
+		&mut self,
+		app_id: u32,
+		visibility: Option<Visibility>,
+	) -> Result<Item, publisher::Error> {
+		let content = self.content();
+		if content.exists() {
+			fs::remove_dir_all(&content).map_err(|e| Error::Write(content.clone(), e))?;
+		}
+		fs::create_dir_all(&content).map_err(|e| Error::Write(content.clone(), e))?;
+		let dummy = content.join(".modinfo");
+		fs::write(&dummy, "name = \"dummy\"").map_err(|e| Error::Write(dummy, e))?;
+		let preview = self.preview();
+		fs::write(&preview, &include_bytes!("../assets/empty.png")[..])
+			.map_err(|e| Error::Write(preview.clone(), e))?;
+
+		let id = self.run(&Vdf {
+			appid:           app_id,
+			publishedfileid: 0,
+			contentfolder:   &content,
+			previewfile:     &preview,
+			visibility:      visibility.map(Visibility::as_steam),
+			title:           "dummy",
+			description:     "dummy",
+			tags:            &[],
+			changenote:      "",
+		})?;
+		Ok(steam::Item(id).into())
+	}
+
+	fn write_file(&mut self, file: RemoteFile, data: &[u8]) -> Result<(), publisher::Error> {
+		match file {
+			RemoteFile::Zip => {
+				let content = self.content();
+				if content.exists() {
+					fs::remove_dir_all(&content).map_err(|e| Error::Write(content.clone(), e))?;
+				}
+				fs::create_dir_all(&content).map_err(|e| Error::Write(content.clone(), e))?;
+				download::unzip(Cursor::new(data), &content).map_err(Error::Extract)?;
+			},
+			RemoteFile::Preview => {
+				let path = self.preview();
+				fs::write(&path, data).map_err(|e| Error::Write(path, e))?;
+			},
+		}
+		Ok(())
+	}
+
+	fn update_item(&mut self, item: Item, artefacts: &Artefacts) -> Result<(), publisher::Error> {
+		self.run(&Vdf {
+			appid:           artefacts.app_id,
+			publishedfileid: item.0,
+			contentfolder:   &self.content(),
+			previewfile:     &self.preview(),
+			visibility:      artefacts.visibility.map(Visibility::as_steam),
+			title:           &artefacts.title,
+			description:     &artefacts.description,
+			tags:            &artefacts.tags,
+			changenote:      &artefacts.change_description,
+		})?;
+		Ok(())
+	}
+
+	fn gallery(&self) -> Result<(), publisher::Error> {
+		// `workshop_build_item` only knows about the main preview
+		Err(publisher::Error::Unsupported("steamcmd"))
+	}
+
+	fn update_gallery(&mut self, _: Item, _: &Artefacts) -> Result<(), publisher::Error> {
+		self.gallery()
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+	let tags = ["Gameplay Tweak".to_owned(), "Map".to_owned()];
+	let vdf = Vdf {
+		appid:           4920,
+		publishedfileid: 1234,
+		contentfolder:   Path::new("/tmp/content"),
+		previewfile:     Path::new("/tmp/preview"),
+		visibility:      Some(2),
+		title:           "My \"mod\"",
+		description:     "[b]Mod ID: 4D2[/b]\nC:\\path",
+		tags:            &tags,
+		changenote:      "git commit: abc",
+	};
+	assert_eq!(
+		vdf.to_string(),
+		"\
+\"workshopitem\"
+{
+	\"appid\"	\"4920\"
+	\"publishedfileid\"	\"1234\"
+	\"contentfolder\"	\"/tmp/content\"
+	\"previewfile\"	\"/tmp/preview\"
+	\"visibility\"	\"2\"
+	\"title\"	\"My \\\"mod\\\"\"
+	\"description\"	\"[b]Mod ID: 4D2[/b]
+C:\\\\path\"
+	\"tags\"	\"Gameplay Tweak,Map\"
+	\"changenote\"	\"git commit: abc\"
+}
+"
+	);
+}
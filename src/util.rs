@@ -23,3 +23,43 @@ pub fn read_to_string(path: impl AsRef<Path>) -> Result<String, ReadError> {
 		source: e,
 	})
 }
+
+/// A directory for the files of a test, removed again when it is dropped,
+/// even if the test fails.
+#[cfg(test)]
+pub struct TempDir(PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+	/// Creates the empty directory `laspad-<name>-test-<pid>` in the temporary directory.
+	pub fn new(name: &str) -> Self {
+		let name = format!("laspad-{}-test-{}", name, std::process::id());
+		let path = std::env::temp_dir().join(name);
+		let _ = fs::remove_dir_all(&path);
+		fs::create_dir_all(&path).expect("Could not create the test directory");
+		TempDir(path)
+	}
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TempDir {
+	type Target = Path;
+
+	fn deref(&self) -> &Path {
+		&self.0
+	}
+}
+
+#[cfg(test)]
+impl AsRef<Path> for TempDir {
+	fn as_ref(&self) -> &Path {
+		&self.0
+	}
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+	fn drop(&mut self) {
+		let _ = fs::remove_dir_all(&self.0);
+	}
+}
@@ -0,0 +1,429 @@
+use std::{
+	collections::HashMap,
+	fs,
+	path::{Path, PathBuf},
+};
+use toml::value::{Table, Value};
+
+use crate::{
+	check::{Location, Problem},
+	config::{self, Config, BRANCH_KEYS},
+	guard::GUARD_KEYS,
+	item::Item,
+	scan::{self, Kind},
+	util,
+};
+
+const FILE: &str = "laspad.toml";
+
+/// The top level keys of version 1, version 2 adds `defaults`
+const KEYS: &[&str] = &[
+	"version",
+	"app_id",
+	"source_dir",
+	"output_dir",
+	"dependencies",
+	"branch",
+];
+
+/// The dotted path of `key` in the table at `prefix`.
+fn field(prefix: &str, key: &str) -> String {
+	if prefix.is_empty() {
+		key.into()
+	} else {
+		format!("{}.{}", prefix, key)
+	}
+}
+
+/// Where the keys and tables of a TOML file are, to point at them in problems.
+#[derive(Default)]
+pub struct Locations {
+	file: PathBuf,
+	text: String,
+	/// The byte offsets of the keys and table headers, by their dotted paths
+	keys: HashMap<String, usize>,
+}
+
+impl Locations {
+	pub fn new(file: impl Into<PathBuf>, text: &str) -> Self {
+		let lines: Vec<&str> = text.split('\n').collect();
+		let mut keys = HashMap::new();
+		let mut start = 0;
+		for (line, scanned) in lines.iter().zip(scan::lines(&lines)) {
+			if scanned.kind == Kind::Header || scanned.kind == Kind::Key {
+				keys.entry(scanned.path.join("."))
+					.or_insert(start + scanned.indent);
+			}
+			start += line.len() + 1;
+		}
+
+		Locations {
+			file: file.into(),
+			text: text.into(),
+			keys,
+		}
+	}
+
+	/// The locations in the project's `laspad.toml`, none if it can't be read.
+	pub fn read(path: &Path) -> Self {
+		fs::read_to_string(path.join(FILE))
+			.map_or_else(|_| Locations::default(), |text| Locations::new(FILE, &text))
+	}
+
+	fn at(&self, offset: usize) -> Location {
+		let before = &self.text[..offset];
+		let start = before.rfind('\n').map_or(0, |i| i + 1);
+		Location {
+			file:   self.file.clone(),
+			line:   before.matches('\n').count() + 1,
+			column: before[start..].chars().count() + 1,
+		}
+	}
+
+	fn offset(&self, mut field: &str) -> Option<usize> {
+		loop {
+			if let Some(&offset) = self.keys.get(field) {
+				return Some(offset);
+			}
+			field = &field[..field.rfind('.')?];
+		}
+	}
+
+	/// The location of `field`, or of the nearest table containing it.
+	pub fn get(&self, field: &str) -> Option<Location> {
+		self.offset(field).map(|offset| self.at(offset))
+	}
+
+	/// The location of the string `value` in `field`.
+	pub fn find(&self, field: &str, value: &str) -> Option<Location> {
+		let offset = self.offset(field)?;
+		let found = [format!("\"{}\"", value), format!("'{}'", value)]
+			.iter()
+			.filter_map(|quoted| self.text[offset..].find(quoted.as_str()))
+			.min()?;
+		Some(self.at(offset + found))
+	}
+}
+
+struct Validator<'a> {
+	root:      &'a Path,
+	locations: Locations,
+	problems:  Vec<Problem>,
+}
+
+impl<'a> Validator<'a> {
+	fn problem(&mut self, field: &str, location: Option<Location>, message: String) {
+		let location = location.or_else(|| self.locations.get(field));
+		self.problems.push(Problem {
+			field: field.into(),
+			message,
+			location,
+		});
+	}
+
+	/// Reports the keys of `table` that aren't in `known`.
+	fn unknown_keys(&mut self, prefix: &str, table: &Table, known: &[&str]) {
+		for key in table.keys().filter(|k| !known.contains(&k.as_str())) {
+			let message = match config::suggest(key, known) {
+				Some(suggestion) => format!("Unknown key, did you mean '{}'?", suggestion),
+				None => "Unknown key".into(),
+			};
+			self.problem(&field(prefix, key), None, message);
+		}
+	}
+
+	/// Reports the paths in `value` that don't exist.
+	fn exist(&mut self, field: &str, value: Option<&Value>) {
+		let paths: Vec<&str> = match value {
+			Some(Value::String(path)) => vec![path.as_str()],
+			Some(Value::Array(paths)) => paths.iter().filter_map(|p| p.as_str()).collect(),
+			_ => return,
+		};
+		for path in paths {
+			if !self.root.join(path).exists() {
+				let location = self.locations.find(field, path);
+				self.problem(field, location, format!("'{}' does not exist", path));
+			}
+		}
+	}
+
+	fn dependencies(&mut self, field: &str, value: Option<&Value>) {
+		let deps = match value {
+			Some(Value::Array(deps)) => deps,
+			_ => return,
+		};
+		for dep in deps {
+			match dep {
+				Value::String(item) => {
+					if let Err(e) = item.parse::<Item>() {
+						let location = self.locations.find(field, item);
+						self.problem(field, location, format!("'{}': {}", item, e));
+					}
+				},
+				Value::Table(dep) if dep.contains_key("path") => {
+					self.unknown_keys(field, dep, &["path"]);
+					self.exist(field, dep.get("path"));
+				},
+				Value::Table(dep) if dep.contains_key("git") => {
+					self.unknown_keys(field, dep, &["git", "rev", "subdir"]);
+				},
+				_ => self.problem(
+					field,
+					None,
+					"A dependency is either a workshop item or a table with `git` or `path`".into(),
+				),
+			}
+		}
+	}
+
+	fn branch(&mut self, prefix: &str, branch: &Table, extends: bool) {
+		let mut known = BRANCH_KEYS.to_vec();
+		if extends {
+			known.push("extends");
+		}
+		self.unknown_keys(prefix, branch, &known);
+		if let Some(Value::Table(guard)) = branch.get("guard") {
+			self.unknown_keys(&field(prefix, "guard"), guard, GUARD_KEYS);
+		}
+
+		for key in &["description", "preview", "overlays"] {
+			self.exist(&field(prefix, key), branch.get(*key));
+		}
+		match branch.get("changelog").and_then(|c| c.as_str()) {
+			None | Some("git") | Some("conventional") => (),
+			Some(_) => self.exist(&field(prefix, "changelog"), branch.get("changelog")),
+		}
+		for key in &["dependencies", "remove_dependencies"] {
+			self.dependencies(&field(prefix, key), branch.get(*key));
+		}
+	}
+
+	/// Reports branches without a name or tags, after inheriting from the branches
+	/// they extend and from `defaults`.
+	fn required(&mut self, prefix: &str, branches: &Table, defaults: &Table) {
+		let tables = branches
+			.iter()
+			.filter_map(|(name, branch)| Some((name.as_str(), branch.as_table()?)));
+		for (name, branch) in tables {
+			let mut chain = vec![(name, branch)];
+			loop {
+				let (last, table) = chain[chain.len() - 1];
+				let parent = match table.get("extends").and_then(|p| p.as_str()) {
+					Some(parent) => parent,
+					None => break,
+				};
+				let extends = field(&field(prefix, last), "extends");
+				if parent == name {
+					let cycle: Vec<_> = chain.iter().map(|&(n, _)| n).chain(Some(name)).collect();
+					let message = format!(
+						"The branches extend each other in a cycle: {}",
+						cycle.join(" -> ")
+					);
+					self.problem(&extends, None, message);
+					break;
+				}
+				if chain.iter().any(|&(n, _)| n == parent) {
+					// A cycle that doesn't contain this branch, which is reported for its own
+					break;
+				}
+				match branches.get(parent).and_then(|p| p.as_table()) {
+					Some(table) => chain.push((parent, table)),
+					None => {
+						// Reported by the branch that extends it
+						if chain.len() == 1 {
+							let names: Vec<&str> = branches.keys().map(|k| k.as_str()).collect();
+							let message = match config::suggest(parent, &names) {
+								Some(s) => {
+									format!("Unknown branch '{}', did you mean '{}'?", parent, s)
+								},
+								None => format!("Unknown branch '{}'", parent),
+							};
+							self.problem(&extends, None, message);
+						}
+						break;
+					},
+				}
+			}
+
+			for key in &["name", "tags"] {
+				let found = chain.iter().any(|(_, t)| t.contains_key(*key));
+				if !found && !defaults.contains_key(*key) {
+					self.problem(
+						&field(prefix, name),
+						None,
+						format!("The branch has no `{}`", key),
+					);
+				}
+			}
+		}
+	}
+}
+
+/// Checks `laspad.toml` strictly, for what laspad would otherwise ignore or report without
+/// a location: unknown keys, missing files, invalid dependencies and incomplete branches.
+pub fn config(path: &Path) -> Result<Vec<Problem>, util::ReadError> {
+	let file = path.join(FILE);
+	if !file.exists() {
+		// mod.settings is parsed strictly, with locations, anyway
+		return Ok(Vec::new());
+	}
+	let text = util::read_to_string(&file)?;
+	let mut validator = Validator {
+		root:      path,
+		locations: Locations::new(FILE, &text),
+		problems:  Vec::new(),
+	};
+
+	let config: Table = match toml::from_str(&text) {
+		Ok(config) => config,
+		Err(e) => {
+			let location = e.line_col().map(|(line, column)| Location {
+				file:   FILE.into(),
+				line:   line + 1,
+				column: column + 1,
+			});
+			validator.problem("", location, e.to_string());
+			return Ok(validator.problems);
+		},
+	};
+
+	let version = match config.get("version") {
+		None => 0,
+		Some(Value::Integer(version)) if *version >= 0 && *version <= Config::VERSION => *version,
+		Some(_) => {
+			let message = format!("The version should be a number up to {}", Config::VERSION);
+			validator.problem("version", None, message);
+			return Ok(validator.problems);
+		},
+	};
+
+	let empty = Table::new();
+	if version == 0 {
+		// The branches are at the top level
+		for (name, branch) in config.iter().filter(|(k, _)| *k != "version") {
+			match branch {
+				Value::Table(branch) => validator.branch(name, branch, false),
+				_ => validator.problem(name, None, "A branch should be a table".into()),
+			}
+		}
+		validator.required("", &config, &empty);
+		return Ok(validator.problems);
+	}
+
+	let mut known = KEYS.to_vec();
+	if version >= 2 {
+		known.push("defaults");
+	}
+	validator.unknown_keys("", &config, &known);
+	validator.dependencies("dependencies", config.get("dependencies"));
+
+	let defaults = match config.get("defaults") {
+		Some(Value::Table(defaults)) if version >= 2 => {
+			validator.branch("defaults", defaults, false);
+			if defaults.contains_key("item") {
+				let message = "Every branch is a workshop item of its own, so `item` is not inherited";
+				validator.problem("defaults.item", None, message.into());
+			}
+			defaults
+		},
+		_ => &empty,
+	};
+	match config.get("branch") {
+		Some(Value::Table(branches)) => {
+			for (name, branch) in branches {
+				let prefix = field("branch", name);
+				match branch {
+					Value::Table(branch) => validator.branch(&prefix, branch, version >= 2),
+					_ => validator.problem(&prefix, None, "A branch should be a table".into()),
+				}
+			}
+			validator.required("branch", branches, defaults);
+		},
+		Some(_) => validator.problem("branch", None, "Should be a table of branches".into()),
+		None => validator.problem(
+			"",
+			None,
+			"There are no branches, add [branch.master]".into(),
+		),
+	}
+
+	Ok(validator.problems)
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+	let root = crate::util::TempDir::new("validate");
+	fs::write(root.join("preview.jpg"), "").unwrap();
+	fs::write(
+		root.join(FILE),
+		r#"version = 2
+dependencies = [
+	"1A", # Some mod
+	"not an item",
+	{ path = "missing" },
+]
+description = """
+[branch.fake]
+"""
+
+[defaults]
+tags = []
+item = "1B"
+
+[branch.master]
+name           = "Test"
+autodescripton = false
+preview        = 'preview.jpg'
+description    = "description.md"
+[branch.master.guard]
+dirt = "warn"
+
+[branch.beta]
+extends = "mastr"
+[branch."a.b"]
+extends = "c"
+[branch.c]
+extends = "a.b"
+"#,
+	)
+	.unwrap();
+
+	let problems: Vec<String> = config(&root)
+		.unwrap()
+		.iter()
+		.map(|p| p.to_string())
+		.collect();
+	assert_eq!(
+		problems,
+		vec![
+			"laspad.toml:7:1: description: Unknown key",
+			"laspad.toml:4:2: dependencies: 'not an item': \
+			 Could not find any workshop item ID in the input",
+			"laspad.toml:5:11: dependencies: 'missing' does not exist",
+			"laspad.toml:13:1: defaults.item: \
+			 Every branch is a workshop item of its own, so `item` is not inherited",
+			"laspad.toml:17:1: branch.master.autodescripton: \
+			 Unknown key, did you mean 'autodescription'?",
+			"laspad.toml:21:1: branch.master.guard.dirt: Unknown key, did you mean 'dirty'?",
+			"laspad.toml:19:18: branch.master.description: 'description.md' does not exist",
+			"laspad.toml:26:1: branch.a.b.extends: \
+			 The branches extend each other in a cycle: a.b -> c -> a.b",
+			"laspad.toml:25:1: branch.a.b: The branch has no `name`",
+			"laspad.toml:24:1: branch.beta.extends: Unknown branch 'mastr', did you mean 'master'?",
+			"laspad.toml:23:1: branch.beta: The branch has no `name`",
+			"laspad.toml:28:1: branch.c.extends: \
+			 The branches extend each other in a cycle: c -> a.b -> c",
+			"laspad.toml:27:1: branch.c: The branch has no `name`",
+		]
+	);
+
+	fs::write(
+		root.join(FILE),
+		"version = 2\n[branch.master]\nname = \"a\ntags = []\n",
+	)
+	.unwrap();
+	let problems = config(&root).unwrap();
+	assert_eq!(problems.len(), 1);
+	assert_eq!(problems[0].location.as_ref().unwrap().line, 3);
+}
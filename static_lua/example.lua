@@ -6,3 +6,23 @@ image       = "preview.jpg"
 tag_modtype = "Gameplay Tweak"
 tag_support = "Must be run on Server"
 publish_id = "4292cdec"
+
+-- Not part of Launch Pad's settings, but valid in them
+--[[ A long
+comment ]]
+long_description = [[
+Fixes -- not a comment]] .. '\nand "more" \195\169\t' .. 5 .. " v" .. 1.5
+version = -12.5e1; build = 0xFF
+multiline = [[
+first
+second]] .. "\
+third"
+enabled = true
+kind    = nil
+extra   = {
+	"a", key = "value";
+	"b",
+	["with space"] = 1,
+	nested = { false },
+	"c",
+}
@@ -1,45 +1,882 @@
-#[macro_use]
-extern crate nom;
-
-use nom::{types::CompleteStr, Err};
-
-use std::collections::HashMap;
-
-named!(ident<CompleteStr, CompleteStr>, take_while!(|c: char| c.is_alphanumeric() || c == '_'));
-
-named!(space<CompleteStr, CompleteStr>, take_while!(char::is_whitespace));
-
-named!(declaration<CompleteStr, (CompleteStr, CompleteStr)>,
-	do_parse!(
-		opt!(space) >>
-		key: ident >>
-		opt!(space) >>
-		tag!("=") >>
-		opt!(space) >>
-		val: alt!(
-			delimited!(
-				tag!("\""),
-				take_until!("\""),
-				tag!("\"")
-			) |
-			do_parse!(
-				tag!("[") >>
-				spaces: fold_many0!(tag!("="), 0, |acc: usize, _| acc + 1) >>
-				tag!("[") >>
-				content: take_until_and_consume!(("]".to_owned() + &"=".repeat(spaces) + "]").as_str()) >>
-				(content)
-			)
-		) >>
-		(key, val)
-	)
-);
-
-named!(declarations<CompleteStr, Vec<(CompleteStr, CompleteStr)> >, many0!(declaration));
-
-pub type Error<'a> = Err<CompleteStr<'a>>;
-pub fn parse(input: &str) -> Result<HashMap<&str, &str>, Error> {
-	let decls = declarations(input.into())?;
-	Ok(decls.1.into_iter().map(|(a, b)| (*a, *b)).collect())
+//! A parser for the literal subset of Lua used by settings files like Launch Pad's `mod.settings`:
+//! assignments of `nil`, booleans, numbers, strings and tables to global names.
+
+use std::{borrow::Cow, collections::HashMap, error, fmt, ops::Range};
+
+/// A Lua value. Numbers are floating point, as in Lua 5.1.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value<'a> {
+	Nil,
+	Boolean(bool),
+	Number(f64),
+	/// Borrows from the input unless it has escapes or is concatenated
+	String(Cow<'a, str>),
+	Table(Table<'a>),
+}
+
+impl<'a> Value<'a> {
+	pub fn as_str(&self) -> Option<&str> {
+		match self {
+			Value::String(s) => Some(s),
+			_ => None,
+		}
+	}
+
+	pub fn as_table(&self) -> Option<&Table<'a>> {
+		match self {
+			Value::Table(t) => Some(t),
+			_ => None,
+		}
+	}
+
+	/// The name of the type, as returned by Lua's `type`.
+	pub fn type_name(&self) -> &'static str {
+		match self {
+			Value::Nil => "nil",
+			Value::Boolean(_) => "boolean",
+			Value::Number(_) => "number",
+			Value::String(_) => "string",
+			Value::Table(_) => "table",
+		}
+	}
+}
+
+/// A Lua table, with its fields in the order they are written in.
+/// Positional fields have the keys `1`, `2` and so on.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Table<'a> {
+	pub fields: Vec<(Value<'a>, Value<'a>)>,
+}
+
+impl<'a> Table<'a> {
+	/// The value of the last field with the key, like Lua does.
+	pub fn get(&self, key: &str) -> Option<&Value<'a>> {
+		self.fields
+			.iter()
+			.rev()
+			.find(|(k, _)| k.as_str() == Some(key))
+			.map(|(_, v)| v)
+	}
+
+	/// The positional fields, i.e. the sequence `1`, `2` and so on.
+	pub fn array(&self) -> Vec<&Value<'a>> {
+		let mut array = Vec::new();
+		while let Some(value) = self
+			.fields
+			.iter()
+			.rev()
+			.find(|(k, _)| *k == Value::Number((array.len() + 1) as f64))
+			.map(|(_, v)| v)
+		{
+			array.push(value);
+		}
+		array
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+	UnexpectedCharacter(char),
+	UnexpectedEnd,
+	UnfinishedString,
+	UnfinishedLongString,
+	UnfinishedComment,
+	InvalidEscape,
+	/// Escapes that don't make up valid UTF-8
+	InvalidUtf8,
+	MalformedNumber,
+	/// The expected token and the one that was found
+	Expected(&'static str, String),
+	/// Concatenation of a value of this type
+	Concatenate(&'static str),
+	/// A table key that is `nil` or NaN
+	InvalidKey,
+	/// Arithmetic on a value of this type
+	Arithmetic(&'static str),
+}
+
+impl fmt::Display for ErrorKind {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ErrorKind::UnexpectedCharacter(c) => write!(f, "unexpected character {:?}", c),
+			ErrorKind::UnexpectedEnd => write!(f, "unexpected end of input"),
+			ErrorKind::UnfinishedString => write!(f, "unfinished string"),
+			ErrorKind::UnfinishedLongString => write!(f, "unfinished long string"),
+			ErrorKind::UnfinishedComment => write!(f, "unfinished long comment"),
+			ErrorKind::InvalidEscape => write!(f, "invalid escape sequence"),
+			ErrorKind::InvalidUtf8 => write!(f, "string is not valid UTF-8"),
+			ErrorKind::MalformedNumber => write!(f, "malformed number"),
+			ErrorKind::Expected(expected, found) => {
+				write!(f, "expected {}, found {}", expected, found)
+			},
+			ErrorKind::Concatenate(t) => write!(f, "attempt to concatenate a {} value", t),
+			ErrorKind::InvalidKey => write!(f, "table index is nil or NaN"),
+			ErrorKind::Arithmetic(t) => write!(f, "attempt to perform arithmetic on a {} value", t),
+		}
+	}
+}
+
+/// A syntax error, with the position it was found at.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Error {
+	pub kind:    ErrorKind,
+	/// Starting at 1
+	pub line:    usize,
+	/// In characters, starting at 1
+	pub column:  usize,
+	/// The line of the input the error is in
+	pub snippet: String,
+}
+
+impl Error {
+	fn new(input: &str, pos: usize, kind: ErrorKind) -> Self {
+		let start = input[..pos].rfind('\n').map_or(0, |i| i + 1);
+		let end = input[pos..].find('\n').map_or(input.len(), |i| pos + i);
+		Error {
+			kind,
+			line:    input[..pos].matches('\n').count() + 1,
+			column:  input[start..pos].chars().count() + 1,
+			snippet: input[start..end].trim_end_matches('\r').to_owned(),
+		}
+	}
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		writeln!(
+			f,
+			"{} at line {}, column {}",
+			self.kind, self.line, self.column
+		)?;
+		writeln!(f, "{}", self.snippet)?;
+		// Keep tabs so that the caret lines up
+		let indent: String = self
+			.snippet
+			.chars()
+			.take(self.column - 1)
+			.map(|c| if c == '\t' { '\t' } else { ' ' })
+			.collect();
+		write!(f, "{}^", indent)
+	}
+}
+
+impl error::Error for Error {}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// What Lua reads as a line break, the longest first
+const LINE_BREAKS: [&str; 4] = ["\r\n", "\n\r", "\n", "\r"];
+
+struct Parser<'a> {
+	input: &'a str,
+	pos:   usize,
+	/// Where the last value ended, before any whitespace and comments after it
+	end:   usize,
+}
+
+impl<'a> Parser<'a> {
+	fn error(&self, pos: usize, kind: ErrorKind) -> Error {
+		Error::new(self.input, pos, kind)
+	}
+
+	fn rest(&self) -> &'a str {
+		&self.input[self.pos..]
+	}
+
+	fn peek(&self) -> Option<char> {
+		self.rest().chars().next()
+	}
+
+	fn eat(&mut self, s: &str) -> bool {
+		if self.rest().starts_with(s) {
+			self.pos += s.len();
+			true
+		} else {
+			false
+		}
+	}
+
+	/// A description of the token at the position, for errors.
+	fn found(&self) -> String {
+		match self.peek() {
+			Some(c) if c.is_alphanumeric() || c == '_' => {
+				let word: String = self
+					.rest()
+					.chars()
+					.take_while(|&c| c.is_alphanumeric() || c == '_')
+					.collect();
+				format!("'{}'", word)
+			},
+			Some(c) => format!("{:?}", c),
+			None => "the end of the input".into(),
+		}
+	}
+
+	fn expect(&mut self, s: &'static str, expected: &'static str) -> Result<()> {
+		if self.eat(s) {
+			Ok(())
+		} else {
+			Err(self.error(self.pos, ErrorKind::Expected(expected, self.found())))
+		}
+	}
+
+	/// The level of the long bracket at the position, e.g. 2 for `[==[`.
+	fn long_bracket(&self) -> Option<usize> {
+		let rest = self.rest();
+		if !rest.starts_with('[') {
+			return None;
+		}
+		let level = rest[1..].chars().take_while(|&c| c == '=').count();
+		if rest[1 + level..].starts_with('[') {
+			Some(level)
+		} else {
+			None
+		}
+	}
+
+	/// Skips a line break at the position, which can be `\n`, `\r`, `\r\n` or `\n\r`.
+	fn line_break(&mut self) -> bool {
+		let rest = self.rest();
+		match LINE_BREAKS.iter().find(|b| rest.starts_with(*b)) {
+			Some(b) => {
+				self.pos += b.len();
+				true
+			},
+			None => false,
+		}
+	}
+
+	/// The content of the long bracket at the position.
+	fn long_string(&mut self, level: usize, unfinished: ErrorKind) -> Result<Cow<'a, str>> {
+		let start = self.pos;
+		self.pos += level + 2;
+		// A line break right after the opening bracket is skipped
+		self.line_break();
+		let close = format!("]{}]", "=".repeat(level));
+		match self.rest().find(&close) {
+			Some(end) => {
+				let content = &self.rest()[..end];
+				self.pos += end + close.len();
+				if !content.contains('\r') {
+					return Ok(Cow::Borrowed(content));
+				}
+				// Like Lua, read every line break as `\n`
+				let mut parser = Parser {
+					input: content,
+					pos:   0,
+					end:   0,
+				};
+				let mut normalized = String::with_capacity(content.len());
+				while let Some(c) = parser.peek() {
+					if parser.line_break() {
+						normalized.push('\n');
+					} else {
+						normalized.push(c);
+						parser.pos += c.len_utf8();
+					}
+				}
+				Ok(Cow::Owned(normalized))
+			},
+			None => Err(self.error(start, unfinished)),
+		}
+	}
+
+	/// Skips whitespace and comments.
+	fn skip(&mut self) -> Result<()> {
+		loop {
+			let trimmed = self.rest().trim_start();
+			self.pos = self.input.len() - trimmed.len();
+			if !self.rest().starts_with("--") {
+				return Ok(());
+			}
+			let start = self.pos;
+			self.pos += 2;
+			match self.long_bracket() {
+				Some(level) => {
+					self.long_string(level, ErrorKind::UnfinishedComment)
+						.map_err(|e| self.error(start, e.kind))?;
+				},
+				None => {
+					self.pos = self
+						.rest()
+						.find(['\n', '\r'])
+						.map_or(self.input.len(), |i| self.pos + i);
+				},
+			}
+		}
+	}
+
+	fn name(&mut self) -> Option<&'a str> {
+		let rest = self.rest();
+		match rest.chars().next() {
+			Some(c) if c.is_alphabetic() || c == '_' => (),
+			_ => return None,
+		}
+		let len = rest
+			.find(|c: char| !(c.is_alphanumeric() || c == '_'))
+			.unwrap_or(rest.len());
+		self.pos += len;
+		Some(&rest[..len])
+	}
+
+	fn number(&mut self) -> Result<f64> {
+		let start = self.pos;
+		let rest = self.rest();
+		let hex = rest.starts_with("0x") || rest.starts_with("0X");
+		let mut len = if hex { 2 } else { 0 };
+		let mut previous = ' ';
+		for c in rest[len..].chars() {
+			let exponent = !hex && (previous == 'e' || previous == 'E');
+			if c.is_ascii_alphanumeric() || c == '.' || (exponent && (c == '+' || c == '-')) {
+				len += 1;
+				previous = c;
+			} else {
+				break;
+			}
+		}
+		let text = &rest[..len];
+		self.pos += len;
+		let number = if hex {
+			u64::from_str_radix(&text[2..], 16).ok().map(|n| n as f64)
+		} else {
+			text.parse().ok()
+		};
+		number.ok_or_else(|| self.error(start, ErrorKind::MalformedNumber))
+	}
+
+	fn string(&mut self, quote: char) -> Result<Cow<'a, str>> {
+		let start = self.pos;
+		self.pos += 1;
+		let content_start = self.pos;
+		// Escapes can produce single bytes of UTF-8 sequences, like `\195\169`
+		let mut owned: Option<Vec<u8>> = None;
+		loop {
+			let c = match self.peek() {
+				Some('\n') | Some('\r') | None => {
+					return Err(self.error(start, ErrorKind::UnfinishedString))
+				},
+				Some(c) => c,
+			};
+			if c == quote {
+				let content = &self.input[content_start..self.pos];
+				self.pos += 1;
+				return match owned {
+					None => Ok(Cow::Borrowed(content)),
+					Some(bytes) => String::from_utf8(bytes)
+						.map(Cow::Owned)
+						.map_err(|_| self.error(start, ErrorKind::InvalidUtf8)),
+				};
+			}
+			if c != '\\' {
+				self.pos += c.len_utf8();
+				if let Some(bytes) = &mut owned {
+					bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+				}
+				continue;
+			}
+
+			let escape = self.pos;
+			let input = self.input;
+			let bytes =
+				owned.get_or_insert_with(|| input.as_bytes()[content_start..escape].to_vec());
+			self.pos += 1;
+			let c = match self.peek() {
+				Some(c) => c,
+				None => return Err(Error::new(input, start, ErrorKind::UnfinishedString)),
+			};
+			self.pos += c.len_utf8();
+			let escaped = match c {
+				'n' => b'\n',
+				'\n' | '\r' => {
+					// An escaped line break, which can be two characters long
+					self.pos -= 1;
+					self.line_break();
+					b'\n'
+				},
+				't' => b'\t',
+				'r' => b'\r',
+				'a' => 0x07,
+				'b' => 0x08,
+				'f' => 0x0C,
+				'v' => 0x0B,
+				'\\' | '"' | '\'' => c as u8,
+				'x' => {
+					let digits = input[self.pos..]
+						.get(..2)
+						.filter(|d| d.chars().all(|c| c.is_ascii_hexdigit()));
+					match digits {
+						Some(digits) => {
+							self.pos += 2;
+							u8::from_str_radix(digits, 16).expect("Invalid hex digits")
+						},
+						None => return Err(Error::new(input, escape, ErrorKind::InvalidEscape)),
+					}
+				},
+				'0'..='9' => {
+					let digits: String = input[self.pos - 1..]
+						.chars()
+						.take(3)
+						.take_while(char::is_ascii_digit)
+						.collect();
+					self.pos += digits.len() - 1;
+					match digits.parse() {
+						Ok(byte) => byte,
+						Err(_) => return Err(Error::new(input, escape, ErrorKind::InvalidEscape)),
+					}
+				},
+				_ => return Err(Error::new(input, escape, ErrorKind::InvalidEscape)),
+			};
+			bytes.push(escaped);
+		}
+	}
+
+	fn table(&mut self) -> Result<Table<'a>> {
+		let start = self.pos;
+		self.pos += 1;
+		let mut table = Table::default();
+		let mut index = 0;
+		loop {
+			self.skip()?;
+			if self.eat("}") {
+				return Ok(table);
+			}
+			if self.peek().is_none() {
+				return Err(self.error(start, ErrorKind::Expected("'}'", self.found())));
+			}
+
+			let key_pos = self.pos;
+			let field = if self.long_bracket().is_none() && self.eat("[") {
+				let key = self.expression()?;
+				self.skip()?;
+				self.expect("]", "']'")?;
+				self.skip()?;
+				self.expect("=", "'='")?;
+				Some(key)
+			} else {
+				let before = self.pos;
+				match self.name() {
+					Some(name) if !is_keyword(name) => {
+						self.skip()?;
+						if self.eat("=") && !self.rest().starts_with('=') {
+							Some(Value::String(name.into()))
+						} else {
+							self.pos = before;
+							None
+						}
+					},
+					_ => {
+						self.pos = before;
+						None
+					},
+				}
+			};
+			let (key, value) = match field {
+				Some(key) => (key, self.expression()?),
+				None => {
+					index += 1;
+					(Value::Number(index as f64), self.expression()?)
+				},
+			};
+			match key {
+				Value::Nil => return Err(self.error(key_pos, ErrorKind::InvalidKey)),
+				Value::Number(n) if n.is_nan() => {
+					return Err(self.error(key_pos, ErrorKind::InvalidKey))
+				},
+				_ => (),
+			}
+			table.fields.push((key, value));
+
+			self.skip()?;
+			if !self.eat(",") && !self.eat(";") {
+				self.expect("}", "',' or '}'")?;
+				return Ok(table);
+			}
+		}
+	}
+
+	/// A single value or a division of numbers, like `0/0`, remembering where it ends.
+	fn term(&mut self) -> Result<Value<'a>> {
+		self.skip()?;
+		let mut start = self.pos;
+		let mut value = self.value()?;
+		self.end = self.pos;
+		loop {
+			self.skip()?;
+			if !self.eat("/") {
+				self.pos = self.end;
+				return Ok(value);
+			}
+			let dividend = match value {
+				Value::Number(n) => n,
+				other => return Err(self.error(start, ErrorKind::Arithmetic(other.type_name()))),
+			};
+			self.skip()?;
+			start = self.pos;
+			value = match self.value()? {
+				Value::Number(n) => Value::Number(dividend / n),
+				other => return Err(self.error(start, ErrorKind::Arithmetic(other.type_name()))),
+			};
+			self.end = self.pos;
+		}
+	}
+
+	fn value(&mut self) -> Result<Value<'a>> {
+		self.skip()?;
+		let start = self.pos;
+		let c = self
+			.peek()
+			.ok_or_else(|| self.error(start, ErrorKind::UnexpectedEnd))?;
+		match c {
+			'"' | '\'' => self.string(c).map(Value::String),
+			'{' => self.table().map(Value::Table),
+			'[' => match self.long_bracket() {
+				Some(level) => self
+					.long_string(level, ErrorKind::UnfinishedLongString)
+					.map(Value::String),
+				None => Err(self.error(start, ErrorKind::UnexpectedCharacter(c))),
+			},
+			'-' => {
+				self.pos += 1;
+				match self.term()? {
+					Value::Number(n) => Ok(Value::Number(-n)),
+					other => {
+						let found = format!("a {}", other.type_name());
+						Err(self.error(start, ErrorKind::Expected("a number after '-'", found)))
+					},
+				}
+			},
+			'(' => {
+				self.pos += 1;
+				let value = self.expression()?;
+				self.skip()?;
+				self.expect(")", "')'")?;
+				Ok(value)
+			},
+			'0'..='9' | '.' => self.number().map(Value::Number),
+			_ => match self.name() {
+				Some("nil") => Ok(Value::Nil),
+				Some("true") => Ok(Value::Boolean(true)),
+				Some("false") => Ok(Value::Boolean(false)),
+				Some(_) => {
+					self.pos = start;
+					Err(self.error(start, ErrorKind::Expected("a value", self.found())))
+				},
+				None => Err(self.error(start, ErrorKind::UnexpectedCharacter(c))),
+			},
+		}
+	}
+
+	/// Values joined with `..`.
+	fn expression(&mut self) -> Result<Value<'a>> {
+		let start = self.pos;
+		let first = self.term()?;
+		self.skip()?;
+		if !self.rest().starts_with("..") || self.rest().starts_with("...") {
+			return Ok(first);
+		}
+
+		let mut joined = String::new();
+		let mut value = first;
+		loop {
+			match value {
+				Value::String(s) => joined.push_str(&s),
+				Value::Number(n) => joined.push_str(&format_number(n)),
+				other => {
+					return Err(self.error(start, ErrorKind::Concatenate(other.type_name())));
+				},
+			}
+			self.skip()?;
+			if !self.eat("..") {
+				return Ok(Value::String(joined.into()));
+			}
+			value = self.term()?;
+		}
+	}
+
+	fn assignment(&mut self) -> Result<Assignment<'a>> {
+		let start = self.pos;
+		let name = match self.name() {
+			Some("local") => {
+				self.skip()?;
+				self.name()
+			},
+			name => name,
+		};
+		let name = match name {
+			Some(name) if !is_keyword(name) => name,
+			_ => {
+				self.pos = start;
+				return Err(self.error(start, ErrorKind::Expected("a name", self.found())));
+			},
+		};
+		self.skip()?;
+		self.expect("=", "'='")?;
+		self.skip()?;
+		let value_start = self.pos;
+		let value = self.expression()?;
+		let value_end = self.end;
+		self.skip()?;
+		let end = if self.eat(";") { self.pos } else { value_end };
+		Ok(Assignment {
+			name,
+			value,
+			statement: start..end,
+			value_range: value_start..value_end,
+		})
+	}
+}
+
+fn is_keyword(name: &str) -> bool {
+	[
+		"and", "break", "do", "else", "elseif", "end", "false", "for", "function", "if", "in",
+		"local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+	]
+	.contains(&name)
+}
+
+/// Formats a number the way Lua converts it to a string.
+fn format_number(n: f64) -> String {
+	if n.fract() == 0.0 && n.abs() < 1e15 {
+		format!("{}", n as i64)
+	} else {
+		format!("{}", n)
+	}
+}
+
+/// An assignment, with the ranges of the whole statement and of its value in the input.
+struct Assignment<'a> {
+	name:        &'a str,
+	value:       Value<'a>,
+	statement:   Range<usize>,
+	value_range: Range<usize>,
+}
+
+fn assignments(input: &str) -> Result<Vec<Assignment<'_>>> {
+	let mut parser = Parser {
+		input,
+		pos: 0,
+		end: 0,
+	};
+	let mut assignments = Vec::new();
+	// A byte order mark is common in files saved on Windows
+	parser.eat("\u{FEFF}");
+	loop {
+		parser.skip()?;
+		if parser.peek().is_none() {
+			return Ok(assignments);
+		}
+		assignments.push(parser.assignment()?);
+	}
+}
+
+/// Parses the assignments in `input`, a later assignment to a name replacing an earlier one.
+pub fn parse<'a>(input: &'a str) -> Result<HashMap<&'a str, Value<'a>>> {
+	Ok(assignments(input)?
+		.into_iter()
+		.map(|a| (a.name, a.value))
+		.collect())
+}
+
+impl<'a> fmt::Display for Value<'a> {
+	/// Writes the value as Lua source, which `parse` reads back as the same value.
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Value::Nil => write!(f, "nil"),
+			Value::Boolean(b) => write!(f, "{}", b),
+			// Lua has no literals for these, but reads them from expressions
+			Value::Number(n) if n.is_nan() => write!(f, "0/0"),
+			Value::Number(n) if n.is_infinite() => {
+				write!(f, "{}1e999", if *n < 0.0 { "-" } else { "" })
+			},
+			Value::Number(n) => write!(f, "{}", format_number(*n)),
+			Value::String(s)
+				if s.contains('\n') && !s.chars().any(|c| c.is_control() && c != '\n') =>
+			{
+				// Long strings keep multiline text readable, the level is the lowest that fits
+				let level = (0..)
+					.find(|&level| {
+						let close = format!("]{}]", "=".repeat(level));
+						!format!("{}]", s).contains(&close)
+					})
+					.expect("Could not find a level for the long string");
+				let equals = "=".repeat(level);
+				// The first newline of a long string is skipped
+				let newline = if s.starts_with('\n') { "\n" } else { "" };
+				write!(f, "[{}[{}{}]{}]", equals, newline, s, equals)
+			},
+			Value::String(s) => {
+				write!(f, "\"")?;
+				for c in s.chars() {
+					match c {
+						'\\' => write!(f, "\\\\")?,
+						'"' => write!(f, "\\\"")?,
+						'\n' => write!(f, "\\n")?,
+						'\r' => write!(f, "\\r")?,
+						'\t' => write!(f, "\\t")?,
+						// Escapes are bytes, so C1 controls are escaped as their UTF-8 encoding.
+						// Three digits, so that a digit after one isn't read as part of it
+						c if c.is_control() => {
+							for byte in c.encode_utf8(&mut [0; 4]).bytes() {
+								write!(f, "\\{:03}", byte)?;
+							}
+						},
+						c => write!(f, "{}", c)?,
+					}
+				}
+				write!(f, "\"")
+			},
+			Value::Table(table) => {
+				if table.fields.is_empty() {
+					return write!(f, "{{}}");
+				}
+				write!(f, "{{ ")?;
+				let mut index = 0;
+				for (i, (key, value)) in table.fields.iter().enumerate() {
+					if i > 0 {
+						write!(f, ", ")?;
+					}
+					match key {
+						Value::Number(n) if *n == (index + 1) as f64 => index += 1,
+						Value::String(name) if is_name(name) => write!(f, "{} = ", name)?,
+						key => write!(f, "[{}] = ", key)?,
+					}
+					write!(f, "{}", value)?;
+				}
+				write!(f, " }}")
+			},
+		}
+	}
+}
+
+fn is_name(s: &str) -> bool {
+	let mut chars = s.chars();
+	matches!(chars.next(), Some(c) if c.is_alphabetic() || c == '_')
+		&& chars.all(|c| c.is_alphanumeric() || c == '_')
+		&& !is_keyword(s)
+}
+
+/// A settings file whose values can be changed while keeping its comments,
+/// formatting and the order of its assignments.
+#[derive(Clone, Debug, Default)]
+pub struct Document {
+	text:        String,
+	/// The names of the assignments, with the ranges of the statements and of their values
+	assignments: Vec<(String, Range<usize>, Range<usize>)>,
+}
+
+impl Document {
+	pub fn parse(input: &str) -> Result<Self> {
+		let assignments = assignments(input)?
+			.into_iter()
+			.map(|a| (a.name.to_owned(), a.statement, a.value_range))
+			.collect();
+		Ok(Document {
+			text: input.to_owned(),
+			assignments,
+		})
+	}
+
+	/// The names that are assigned to, in order.
+	pub fn names(&self) -> impl Iterator<Item = &str> {
+		self.assignments.iter().map(|(name, ..)| name.as_str())
+	}
+
+	/// Replaces `range` of the text, moving the ranges after it.
+	fn replace(&mut self, range: Range<usize>, with: &str) {
+		let shift = |i: &mut usize| {
+			if *i >= range.end {
+				*i = *i - range.len() + with.len();
+			}
+		};
+		for (_, statement, value) in &mut self.assignments {
+			for i in &mut [
+				&mut statement.start,
+				&mut statement.end,
+				&mut value.start,
+				&mut value.end,
+			] {
+				shift(i);
+			}
+		}
+		self.text.replace_range(range, with);
+	}
+
+	/// The line ending of the text, so that new lines match the others.
+	fn line_ending(&self) -> &'static str {
+		if self.text.contains("\r\n") {
+			"\r\n"
+		} else {
+			"\n"
+		}
+	}
+
+	/// Changes the value of the last assignment to `name`,
+	/// or adds an assignment after the others, aligned like the last one.
+	pub fn set(&mut self, name: &str, value: &Value) {
+		let newline = self.line_ending();
+		// Only long strings have line breaks, which Lua reads the same either way
+		let value = value.to_string().replace('\n', newline);
+		if let Some(i) = self.assignments.iter().rposition(|(n, ..)| n == name) {
+			let range = self.assignments[i].2.clone();
+			self.replace(range.clone(), &value);
+			self.assignments[i].2 = range.start..range.start + value.len();
+			return;
+		}
+
+		let width = self.assignments.last().map_or(0, |(_, statement, value)| {
+			self.text[statement.start..value.start]
+				.find('=')
+				.unwrap_or(0)
+		});
+		if !self.text.is_empty() && !self.text.ends_with('\n') {
+			self.text += newline;
+		}
+		let start = self.text.len();
+		self.text += &format!("{:<1$} = ", name, width.saturating_sub(1));
+		let value_start = self.text.len();
+		self.text += &value;
+		let end = self.text.len();
+		self.text += newline;
+		self.assignments
+			.push((name.to_owned(), start..end, value_start..end));
+	}
+
+	/// Removes the assignments to `name` along with the lines they are alone on.
+	/// Returns whether there were any.
+	pub fn remove(&mut self, name: &str) -> bool {
+		let mut removed = false;
+		while let Some(i) = self.assignments.iter().position(|(n, ..)| n == name) {
+			let (_, statement, _) = self.assignments.remove(i);
+			let line_start = self.text[..statement.start]
+				.rfind('\n')
+				.map_or(0, |i| i + 1);
+			let line_end = self.text[statement.end..]
+				.find('\n')
+				.map_or(self.text.len(), |i| statement.end + i + 1);
+			let alone = self.text[line_start..statement.start].trim().is_empty()
+				&& self.text[statement.end..line_end].trim().is_empty();
+			let blank = |c: char| c == ' ' || c == '\t';
+			let before = self.text[..statement.start].trim_end_matches(blank).len();
+			let after =
+				self.text.len() - self.text[statement.end..].trim_start_matches(blank).len();
+			if alone {
+				self.replace(line_start..line_end, "");
+			} else if self.text[statement.end..line_end].trim().is_empty() {
+				// Also the blanks between the previous statement on the line and this one
+				self.replace(before..statement.end, "");
+			} else {
+				self.replace(statement.start..after, "");
+			}
+			removed = true;
+		}
+		removed
+	}
+}
+
+impl fmt::Display for Document {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(&self.text)
+	}
 }
 
 #[cfg(test)]
@@ -47,12 +884,108 @@ pub fn parse(input: &str) -> Result<HashMap<&str, &str>, Error> {
 fn test() {
 	const EXAMPLE: &str = include_str!("example.lua");
 	let map = parse(EXAMPLE).unwrap();
-	assert_eq!(map["name"], "NS2 Community Fixes");
-	assert_eq!(map["source_dir"], "");
-	assert_eq!(map["output_dir"], "output");
-	assert_eq!(map["description"], "test description[]][");
-	assert_eq!(map["image"], "preview.jpg");
-	assert_eq!(map["tag_modtype"], "Gameplay Tweak");
-	assert_eq!(map["tag_support"], "Must be run on Server");
-	assert_eq!(map["publish_id"], "4292cdec");
+	let s = |key: &str| map[key].as_str().unwrap();
+	assert_eq!(s("name"), "NS2 Community Fixes");
+	assert_eq!(s("source_dir"), "");
+	assert_eq!(s("output_dir"), "output");
+	assert_eq!(s("description"), "test description[]][");
+	assert_eq!(s("image"), "preview.jpg");
+	assert_eq!(s("tag_modtype"), "Gameplay Tweak");
+	assert_eq!(s("tag_support"), "Must be run on Server");
+	assert_eq!(s("publish_id"), "4292cdec");
+	assert_eq!(
+		s("long_description"),
+		"Fixes -- not a comment\nand \"more\" é\t5 v1.5"
+	);
+	// The example has Windows line breaks, which Lua reads as `\n`
+	assert_eq!(s("multiline"), "first\nsecond\nthird");
+	assert_eq!(map["version"], Value::Number(-12.5e1));
+	assert_eq!(map["build"], Value::Number(255.0));
+	assert_eq!(map["enabled"], Value::Boolean(true));
+	assert_eq!(map["kind"], Value::Nil);
+	let table = map["extra"].as_table().unwrap();
+	assert_eq!(table.get("key").unwrap().as_str(), Some("value"));
+	let array: Vec<_> = table
+		.array()
+		.into_iter()
+		.filter_map(|v| v.as_str())
+		.collect();
+	assert_eq!(array, vec!["a", "b", "c"]);
+	assert_eq!(table.fields[3].0, Value::String("with space".into()));
+	assert_eq!(
+		table.get("nested").unwrap().as_table().unwrap().array(),
+		vec![&Value::Boolean(false)]
+	);
+
+	let error = |input| parse(input).unwrap_err();
+	let unfinished = error("a = 'fine'\nname = \"unfinished\n");
+	assert_eq!(unfinished.kind, ErrorKind::UnfinishedString);
+	assert_eq!((unfinished.line, unfinished.column), (2, 8));
+	assert_eq!(unfinished.snippet, "name = \"unfinished");
+	assert_eq!(
+		unfinished.to_string(),
+		"unfinished string at line 2, column 8\nname = \"unfinished\n       ^"
+	);
+	let missing = error("name \"x\"");
+	assert_eq!(missing.kind, ErrorKind::Expected("'='", "'\"'".into()));
+	assert_eq!(missing.column, 6);
+	assert_eq!(
+		error("a = { 1, 2").kind,
+		ErrorKind::Expected("',' or '}'", "the end of the input".into())
+	);
+	assert_eq!(error("a = 'x' .. {}").kind, ErrorKind::Concatenate("table"));
+	assert_eq!(error("a = 1 / 'x'").kind, ErrorKind::Arithmetic("string"));
+	assert_eq!(parse("a = -1/0 .. ''").unwrap()["a"].as_str(), Some("-inf"));
+	assert_eq!(error("a = '\\q'").kind, ErrorKind::InvalidEscape);
+	assert_eq!(error("a = 'x\r\n'").kind, ErrorKind::UnfinishedString);
+	assert_eq!(error("a = 0x").kind, ErrorKind::MalformedNumber);
+	assert_eq!(error("--[[ open").kind, ErrorKind::UnfinishedComment);
+	assert_eq!(
+		error("a = b").kind,
+		ErrorKind::Expected("a value", "'b'".into())
+	);
+
+	let numbers = [f64::INFINITY, f64::NEG_INFINITY, f64::NAN, -0.5, 1e100];
+	let numbers: Vec<_> = numbers.iter().map(|&n| Value::Number(n)).collect();
+	for value in map.values().chain(&numbers) {
+		let source = format!("a = {}", value);
+		match (&parse(&source).unwrap()["a"], value) {
+			(Value::Number(a), Value::Number(b)) if a.is_nan() => assert!(b.is_nan(), "{}", source),
+			(parsed, value) => assert_eq!(parsed, value, "{}", source),
+		}
+	}
+	for s in &["\nnew]]line", "a]", "tab\tand\nnewline", "\u{1}9", "\u{85}", "é\"\\"] {
+		let value = Value::String((*s).into());
+		assert_eq!(parse(&format!("a = {}", value)).unwrap()["a"], value);
+	}
+
+	let mut document = Document::parse(&EXAMPLE.replace("\r\n", "\n")).unwrap();
+	document.set("name", &Value::String("Renamed".into()));
+	document.set("version", &Value::Number(3.0));
+	document.set("new", &Value::Boolean(false));
+	assert!(document.remove("build"));
+	assert!(document.remove("tag_support"));
+	assert!(!document.remove("missing"));
+	let text = document.to_string();
+	assert!(text.starts_with("name        = \"Renamed\"\nsource_dir  = \"\""));
+	assert!(text.contains("-- Not part of Launch Pad's settings, but valid in them\n"));
+	assert!(text.contains("\ntag_modtype = \"Gameplay Tweak\"\npublish_id = "));
+	assert!(text.contains("\nversion = 3;\nmultiline = [[\n"));
+	assert!(text.ends_with("\t\"c\",\n}\nnew     = false\n"));
+	let names: Vec<_> = document.names().collect();
+	assert_eq!(names.last(), Some(&"new"));
+	let map = parse(&text).unwrap();
+	assert_eq!(map["name"].as_str(), Some("Renamed"));
+	assert_eq!(map["new"], Value::Boolean(false));
+	assert!(!map.contains_key("build"));
+	assert_eq!(map["image"].as_str(), Some("preview.jpg"));
+
+	// New lines get the line ending of the document
+	let mut document = Document::parse(EXAMPLE).unwrap();
+	document.set("new", &Value::Boolean(false));
+	document.set("text", &Value::String("a\nb".into()));
+	let text = document.to_string();
+	assert!(!text.replace("\r\n", "").contains('\n'));
+	assert!(text.ends_with("}\r\nnew     = false\r\ntext    = [[a\r\nb]]\r\n"));
+	assert_eq!(parse(&text).unwrap()["text"].as_str(), Some("a\nb"));
 }